@@ -0,0 +1,174 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Numbers the temporary files of a process, so that writes from different threads do not
+/// share one.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Replaces the file at `path` so that it holds either its old or its new contents, never a
+/// part of them: the contents are written to a temporary file next to it, which is then
+/// renamed over it. The file keeps its permissions.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    write_atomic_with(path, |file| file.write_all(contents))
+}
+
+/// Path of the copy `write_with_backup` keeps of the previous contents, e.g. `notes.json.bak`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+/// Replaces the file at `path` like `write_atomic`, keeping what it held before at
+/// `backup_path` with the same permissions.
+pub fn write_with_backup(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    match std::fs::read(path) {
+        Ok(previous) => {
+            let backup = backup_path(path);
+            write_atomic(&backup, &previous)?;
+            std::fs::set_permissions(&backup, std::fs::metadata(path)?.permissions())?;
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    write_atomic(path, contents)
+}
+
+/// Directory holding the file at `path`, the current one for a bare file name like `notes.json`,
+/// whose parent path is empty.
+fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+fn write_atomic_with(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let dir = parent_dir(path);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = dir.join(format!(
+        ".{}.{}.{}.tmp",
+        name,
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        write(&mut file)?;
+        match std::fs::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        File::open(dir)?.sync_all()
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_write_with_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        assert_eq!(backup_path(&path), dir.path().join("notes.json.bak"));
+
+        write_with_backup(&path, b"one").unwrap();
+        assert_eq!(files(dir.path()), ["notes.json"]);
+
+        write_with_backup(&path, b"two").unwrap();
+        write_with_backup(&path, b"three").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"three");
+        assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"two");
+        assert_eq!(files(dir.path()), ["notes.json", "notes.json.bak"]);
+    }
+
+    #[test]
+    fn test_interrupted_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        write_atomic(&path, b"old notes").unwrap();
+
+        // e.g. the disk filled up halfway through
+        let result = write_atomic_with(&path, |file| {
+            file.write_all(b"new no")?;
+            Err(std::io::Error::other("No space left on device"))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old notes");
+        assert_eq!(files(dir.path()), ["notes.json"]);
+
+        // a process that crashed while writing leaves its temporary file behind
+        std::fs::write(dir.path().join(".notes.json.1.tmp"), b"new no").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"old notes");
+        write_with_backup(&path, b"new notes").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new notes");
+        assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"old notes");
+    }
+
+    #[test]
+    fn test_failed_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        // a directory can not be replaced by a file
+        let path = dir.path().join("notes.json");
+        std::fs::create_dir(&path).unwrap();
+
+        assert!(write_atomic(&path, b"notes").is_err());
+        assert!(path.is_dir());
+        assert_eq!(files(dir.path()), ["notes.json"]);
+
+        assert!(write_atomic(&dir.path().join("missing/notes.json"), b"notes").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_keep_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        std::fs::write(&path, b"one").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_with_backup(&path, b"two").unwrap();
+        write_atomic(&path, b"three").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"three");
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&backup_path(&path)), 0o600);
+    }
+
+    #[test]
+    fn test_parent_dir() {
+        assert_eq!(parent_dir(Path::new("notes.json")), Path::new("."));
+        assert_eq!(parent_dir(Path::new("./notes.json")), Path::new("."));
+        assert_eq!(
+            parent_dir(Path::new("/home/a/notes.json")),
+            Path::new("/home/a")
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDate;
+
+use crate::{fs::write_atomic, text::Block};
+
+/// Path of the journal page of `date` in a graph, in Logseq's default `yyyy_MM_dd` file name
+/// format.
+pub fn journal_path(graph: &Path, date: NaiveDate) -> PathBuf {
+    graph
+        .join("journals")
+        .join(date.format("%Y_%m_%d.md").to_string())
+}
+
+/// Appends an outline to the journal page of `date`, keeping what the page has. A page with
+/// only the empty block Logseq creates for new days is replaced.
+pub fn append_to_journal(graph: &Path, date: NaiveDate, outline: &str) -> std::io::Result<PathBuf> {
+    if !graph.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no Logseq graph at {}", graph.display()),
+        ));
+    }
+
+    let path = journal_path(graph, date);
+    std::fs::create_dir_all(path.parent().unwrap())?;
+
+    let mut page = match std::fs::read_to_string(&path) {
+        Ok(page) => page,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    if page.trim() == "-" {
+        page.clear();
+    }
+    if !page.is_empty() && !page.ends_with('\n') {
+        page.push('\n');
+    }
+    page.push_str(outline);
+
+    write_atomic(&path, page.as_bytes())?;
+    Ok(path)
+}
+
+/// Groups blocks by the journal page they belong on, the day `day` gives for the top block of
+/// their subtree, so that children stay under their parent. Gives the indices of the blocks of
+/// every day, in order.
+pub fn group_by_day(
+    blocks: &[Block],
+    day: impl Fn(&Block) -> NaiveDate,
+) -> BTreeMap<NaiveDate, Vec<usize>> {
+    let mut days = BTreeMap::<NaiveDate, Vec<usize>>::new();
+    let mut root: Option<(usize, NaiveDate)> = None;
+
+    for (index, block) in blocks.iter().enumerate() {
+        let date = match root {
+            Some((level, date)) if block.level > level => date,
+            _ => {
+                let date = day(block);
+                root = Some((block.level, date));
+                date
+            }
+        };
+        days.entry(date).or_default().push(index);
+    }
+
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_append_to_journal() {
+        let graph = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd_opt(2025, 3, 7).unwrap();
+        let path = graph.path().join("journals/2025_03_07.md");
+
+        assert_eq!(journal_path(graph.path(), date), path);
+
+        // a new page
+        append_to_journal(graph.path(), date, "- one\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "- one\n");
+
+        // an existing page is kept
+        std::fs::write(&path, "- written in Logseq\n  - child").unwrap();
+        append_to_journal(graph.path(), date, "- two\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "- written in Logseq\n  - child\n- two\n"
+        );
+
+        // the empty block of a new day is replaced
+        std::fs::write(&path, "-\n").unwrap();
+        append_to_journal(graph.path(), date, "- three\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "- three\n");
+
+        // no temporary files are left behind
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            1
+        );
+
+        assert!(append_to_journal(&graph.path().join("missing"), date, "- four\n").is_err());
+    }
+
+    #[test]
+    fn test_group_by_day() {
+        let block = |level: usize, day: u32| Block {
+            level,
+            created: Utc.with_ymd_and_hms(2025, 3, day, 12, 0, 0).unwrap(),
+            ..Default::default()
+        };
+        let date = |day: u32| NaiveDate::from_ymd_opt(2025, 3, day).unwrap();
+        let blocks = [
+            block(0, 7),
+            // a child written later stays with its parent
+            block(1, 9),
+            block(2, 5),
+            block(0, 5),
+            block(0, 7),
+            block(1, 7),
+        ];
+
+        let days = group_by_day(&blocks, |block| block.created.date_naive());
+        assert_eq!(
+            days.into_iter().collect::<Vec<_>>(),
+            vec![(date(5), vec![3]), (date(7), vec![0, 1, 2, 4, 5])]
+        );
+
+        // a lone child is a root of its own
+        let days = group_by_day(&blocks[1..3], |block| block.created.date_naive());
+        assert_eq!(
+            days.into_iter().collect::<Vec<_>>(),
+            vec![(date(9), vec![0, 1])]
+        );
+
+        assert!(group_by_day(&[], |block| block.created.date_naive()).is_empty());
+    }
+}
@@ -0,0 +1,7 @@
+mod journal;
+mod outline;
+mod pages;
+
+pub use journal::*;
+pub use outline::*;
+pub use pages::*;
@@ -0,0 +1,93 @@
+use crate::text::{Block, BlockMode, fix_levels};
+
+/// Renders blocks as a Logseq outline: every block becomes a `- ` bullet, nested with tabs
+/// under its parent as Logseq writes its pages, with its continuation lines indented under
+/// it, and tags become Markdown. Code blocks become fenced code blocks and Markdown blocks
+/// are written as they are. Empty blocks are skipped.
+pub fn outline(blocks: &[Block]) -> String {
+    let blocks = blocks
+        .iter()
+        .filter(|block| !block.content.text.trim().is_empty())
+        .collect::<Vec<_>>();
+    let mut levels = blocks.iter().map(|block| block.level).collect::<Vec<_>>();
+    fix_levels(&mut levels);
+
+    let mut markdown = String::new();
+
+    for (block, level) in blocks.into_iter().zip(levels) {
+        let indent = "\t".repeat(level);
+        let text = match block.mode {
+            BlockMode::Text => block.content.clipboard_text(),
+            BlockMode::Markdown => block.content.text.clone(),
+            mode => format!("```{}\n{}\n```", mode.name(), block.content.text.trim_end()),
+        };
+        for (index, line) in text.trim_end().lines().enumerate() {
+            match (index, line.is_empty()) {
+                (0, _) => markdown.push_str(&format!("{}- ", indent)),
+                (_, false) => markdown.push_str(&format!("{}  ", indent)),
+                (_, true) => {}
+            }
+            markdown.push_str(line);
+            markdown.push('\n');
+        }
+    }
+
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::TextWithTags;
+
+    #[test]
+    fn test_outline() {
+        let block = |level: usize, markdown: &str| Block {
+            level,
+            content: TextWithTags::from_markdown(markdown),
+            ..Default::default()
+        };
+        let blocks = [
+            block(0, "First **block**"),
+            block(0, "  \n"),
+            block(0, "Second ==block==\nwith more lines\n\nand a gap\n"),
+            block(1, "Child\nof the second"),
+            block(3, "Grandchild"),
+            block(0, "Third"),
+        ];
+
+        assert_eq!(
+            outline(&blocks),
+            "- First **block**\n\
+             - Second ==block==\n  with more lines\n\n  and a gap\n\
+             \t- Child\n\t  of the second\n\
+             \t\t- Grandchild\n\
+             - Third\n"
+        );
+        assert_eq!(
+            outline(&blocks[3..5]),
+            "- Child\n  of the second\n\t- Grandchild\n"
+        );
+        assert_eq!(outline(&[]), "");
+    }
+
+    #[test]
+    fn test_outline_modes() {
+        let block = |level: usize, mode: BlockMode, text: &str| Block {
+            level,
+            mode,
+            content: TextWithTags::from_str(text),
+            ..Default::default()
+        };
+        let blocks = [
+            block(0, BlockMode::Markdown, "# Notes\n**raw**"),
+            block(1, BlockMode::Rust, "fn main() {\n    x.a_b_c();\n}\n"),
+        ];
+
+        assert_eq!(
+            outline(&blocks),
+            "- # Notes\n  **raw**\n\
+             \t- ```rust\n\t  fn main() {\n\t      x.a_b_c();\n\t  }\n\t  ```\n"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::Path;
+
+/// Names of the pages of a graph, from the file names in `pages/` or their `title::`
+/// property, sorted and without duplicates.
+pub fn page_names(graph: &Path) -> std::io::Result<Vec<String>> {
+    let mut names = vec![];
+
+    for entry in std::fs::read_dir(graph.join("pages"))? {
+        let path = entry?.path();
+        let is_page = path
+            .extension()
+            .is_some_and(|extension| extension == "md" || extension == "org");
+        if !is_page {
+            continue;
+        }
+
+        let title = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|page| title_property(&page));
+        let name = match title {
+            Some(title) => title,
+            None => page_name_of_file(&path.file_stem().unwrap_or_default().to_string_lossy()),
+        };
+        names.push(name);
+    }
+
+    names.sort_by_cached_key(|name| (name.to_lowercase(), name.clone()));
+    names.dedup_by_key(|name| name.to_lowercase());
+    Ok(names)
+}
+
+/// The `title::` property of a page, which is in the properties block at its top.
+fn title_property(page: &str) -> Option<String> {
+    page.lines()
+        .take_while(|line| line.contains(":: "))
+        .find_map(|line| line.trim_start_matches(['-', ' ']).strip_prefix("title:: "))
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// Logseq writes the `/` of namespaced pages as `___` and percent-encodes characters that
+/// can not be in file names.
+fn page_name_of_file(stem: &str) -> String {
+    let stem = stem.replace("___", "/");
+    let bytes = stem.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).unwrap_or(stem)
+}
+
+/// Page names matching what was typed after `[[`, case-insensitively: the ones starting with
+/// it come first, then the ones containing it.
+pub fn complete<'a>(names: &'a [String], query: &str) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    let (mut prefixed, mut contained) = (vec![], vec![]);
+
+    for name in names {
+        let lower = name.to_lowercase();
+        if lower.starts_with(&query) {
+            prefixed.push(name.as_str());
+        } else if lower.contains(&query) {
+            contained.push(name.as_str());
+        }
+    }
+
+    prefixed.append(&mut contained);
+    prefixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_names() {
+        let graph = tempfile::tempdir().unwrap();
+        let pages = graph.path().join("pages");
+        std::fs::create_dir(&pages).unwrap();
+        for (file, page) in [
+            ("rust.md", "- about rust"),
+            ("project___illpad.md", ""),
+            ("what%3F.md", ""),
+            ("Rust.org", ""),
+            ("x.md", "title:: Renamed Page\nalias:: other\n\n- content"),
+            ("notes.txt", ""),
+        ] {
+            std::fs::write(pages.join(file), page).unwrap();
+        }
+
+        assert_eq!(
+            page_names(graph.path()).unwrap(),
+            vec!["project/illpad", "Renamed Page", "Rust", "what?"]
+        );
+        assert!(page_names(&graph.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_complete() {
+        let names = ["Rust", "Trust", "rustacean", "Go"].map(String::from);
+        assert_eq!(complete(&names, "rus"), vec!["Rust", "rustacean", "Trust"]);
+        assert_eq!(
+            complete(&names, ""),
+            vec!["Rust", "Trust", "rustacean", "Go"]
+        );
+        assert!(complete(&names, "x").is_empty());
+    }
+}
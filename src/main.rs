@@ -1,5 +1,9 @@
 use ui::run_app;
 
+mod fs;
+mod logseq;
+mod notes;
+mod settings;
 mod text;
 mod ui;
 
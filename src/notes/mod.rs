@@ -0,0 +1,197 @@
+mod recover;
+
+pub use recover::*;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::text::Block;
+
+/// Version of `notes.json` this build writes.
+pub const VERSION: u64 = 2;
+
+/// Upgrades `notes.json` of a version to the next one, the first one upgrades version 1.
+const MIGRATIONS: &[fn(Value) -> Value] = &[migrate_1_to_2];
+
+/// What `notes.json` holds.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Notes {
+    pub version: u64,
+    pub blocks: Vec<Block>,
+    /// Settings of the pad itself, kept as they are so that newer versions can add some.
+    #[serde(default)]
+    pub settings: Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub enum NotesError {
+    Json(serde_json::Error),
+    /// Written by a newer illpad, or not by illpad at all.
+    Version(Value),
+}
+
+impl std::fmt::Display for NotesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotesError::Json(err) => write!(f, "{}", err),
+            NotesError::Version(version) => write!(f, "unknown version {}", version),
+        }
+    }
+}
+
+impl From<serde_json::Error> for NotesError {
+    fn from(err: serde_json::Error) -> Self {
+        NotesError::Json(err)
+    }
+}
+
+impl Notes {
+    pub fn new(blocks: Vec<Block>, settings: Map<String, Value>) -> Self {
+        Self {
+            version: VERSION,
+            blocks,
+            settings,
+        }
+    }
+
+    /// Reads `notes.json` of any version this build knows, upgrading it to the current one.
+    pub fn parse(json: &str) -> Result<Self, NotesError> {
+        let mut notes = serde_json::from_str::<Value>(json)?;
+        loop {
+            let version = version(&notes)?;
+            if version == VERSION {
+                return Ok(serde_json::from_value(notes)?);
+            }
+
+            let migration = (version < VERSION)
+                .then(|| MIGRATIONS.get(version as usize - 1))
+                .flatten()
+                .ok_or(NotesError::Version(Value::from(version)))?;
+            notes = migration(notes);
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn version(notes: &Value) -> Result<u64, NotesError> {
+    match notes {
+        Value::Array(_) => Ok(1),
+        Value::Object(object) => {
+            let version = object.get("version").cloned().unwrap_or_default();
+            version
+                .as_u64()
+                .filter(|&version| version > 1)
+                .ok_or(NotesError::Version(version))
+        }
+        _ => Err(NotesError::Version(Value::Null)),
+    }
+}
+
+/// Version 1 is a bare array of blocks, from the first notes with only text and tags to blocks
+/// with ids and times. Fields added to blocks have defaults, so only the envelope is new.
+fn migrate_1_to_2(blocks: Value) -> Value {
+    json!({
+        "version": 2,
+        "blocks": blocks,
+        "settings": {},
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::BlockMode;
+
+    fn texts(notes: &Notes) -> Vec<(usize, BlockMode, &str)> {
+        notes
+            .blocks
+            .iter()
+            .map(|block| (block.level, block.mode, block.content.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_v1_text_only() {
+        let notes = Notes::parse(include_str!("fixtures/v1-text-only.json")).unwrap();
+        assert_eq!(notes.version, VERSION);
+        assert_eq!(
+            texts(&notes),
+            [
+                (0, BlockMode::Text, "First bold block"),
+                (0, BlockMode::Text, "A link")
+            ]
+        );
+        assert_eq!(notes.blocks[0].content.tags[0].name, "bold");
+        assert_eq!(
+            notes.blocks[1].content.tags[0].attrs["href"],
+            "https://example.com"
+        );
+        assert!(notes.settings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_v1_blocks() {
+        let notes = Notes::parse(include_str!("fixtures/v1-blocks.json")).unwrap();
+        assert_eq!(
+            texts(&notes),
+            [
+                (0, BlockMode::Text, "Parent"),
+                (1, BlockMode::Rust, "fn main() {}")
+            ]
+        );
+        assert_eq!(notes.blocks[0].id, "0195701a2b3c00c0ffee");
+        assert_eq!(
+            notes.blocks[0].modified.to_rfc3339(),
+            "2025-03-08T18:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let json = include_str!("fixtures/v2.json");
+        let notes = Notes::parse(json).unwrap();
+        assert_eq!(texts(&notes), [(0, BlockMode::Math, "2 MB * 3")]);
+        assert_eq!(notes.settings["theme"], "dark");
+
+        // what is read is written back as it was
+        assert_eq!(
+            serde_json::from_str::<Value>(&notes.to_json().unwrap()).unwrap(),
+            serde_json::from_str::<Value>(json).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            Notes::parse(include_str!("fixtures/v99.json")),
+            Err(NotesError::Version(version)) if version == 99
+        ));
+        for json in [
+            r#"{"blocks": []}"#,
+            r#"{"version": 0, "blocks": []}"#,
+            r#"{"version": "2", "blocks": []}"#,
+            r#""notes""#,
+        ] {
+            assert!(
+                matches!(Notes::parse(json), Err(NotesError::Version(_))),
+                "{}",
+                json
+            );
+        }
+        for json in [
+            "",
+            "[{\"text\": \"cut off",
+            r#"{"version": 2, "blocks": {}}"#,
+            r#"[{"text": 1}]"#,
+        ] {
+            assert!(
+                matches!(Notes::parse(json), Err(NotesError::Json(_))),
+                "{}",
+                json
+            );
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use super::Notes;
+use crate::{
+    fs::backup_path,
+    text::{Block, TextWithTags},
+};
+
+/// Moves a `notes.json` that can not be read aside to a timestamped copy, e.g.
+/// `notes.json.20250307-093000.corrupt`, so that saving does not overwrite it. Its backup is
+/// copied aside as well, e.g. to `notes.json.bak.20250307-093000.corrupt`, as the second save
+/// replaces it.
+pub fn quarantine(path: &Path) -> std::io::Result<PathBuf> {
+    let stamp = Local::now().format(".%Y%m%d-%H%M%S.corrupt").to_string();
+    let aside = |path: &Path| {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(&stamp);
+        path.with_file_name(name)
+    };
+
+    let backup = backup_path(path);
+    if backup.exists() {
+        std::fs::copy(&backup, aside(&backup))?;
+    }
+    let corrupt_path = aside(path);
+    std::fs::rename(path, &corrupt_path)?;
+    Ok(corrupt_path)
+}
+
+/// Gets what it can out of a `notes.json` that can not be read: all blocks when it reads as
+/// notes, e.g. after a bad byte was replaced, and otherwise the text of every block without its
+/// formatting.
+pub fn recover(json: &str) -> Vec<Block> {
+    if let Ok(notes) = Notes::parse(json) {
+        return notes.blocks;
+    }
+
+    recover_texts(json)
+        .into_iter()
+        .map(|text| TextWithTags::from_str(&text).into())
+        .collect()
+}
+
+/// The values of all `"text"` keys, including the last one of a file that was cut off.
+fn recover_texts(json: &str) -> Vec<String> {
+    let mut texts = vec![];
+    let mut offset = 0;
+
+    while let Some(found) = json[offset..].find("\"text\"") {
+        let key = offset + found;
+        offset = key + "\"text\"".len();
+        // a quote of a string, e.g. `"say \"text\""`
+        if json[..key].ends_with('\\') {
+            continue;
+        }
+
+        let Some(value) = json[offset..].trim_start().strip_prefix(':') else {
+            continue;
+        };
+        let value = value.trim_start();
+        if !value.starts_with('"') {
+            continue;
+        }
+
+        let text = match string_end(value) {
+            Some(end) => {
+                offset = json.len() - value.len() + end;
+                serde_json::from_str(&value[..end])
+                    .unwrap_or_else(|_| unescape_lossy(&value[1..end - 1]))
+            }
+            None => {
+                offset = json.len();
+                unescape_lossy(&value[1..])
+            }
+        };
+        if !text.trim().is_empty() {
+            texts.push(text);
+        }
+    }
+
+    texts
+}
+
+/// Length of the JSON string at the start of `value` with its quotes, `None` when it does not
+/// end.
+fn string_end(value: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, c) in value.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(index + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Decodes the escapes of a JSON string that is not valid, keeping what can not be decoded.
+fn unescape_lossy(value: &str) -> String {
+    let mut text = String::new();
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some('r') => text.push('\r'),
+            Some('u') => {
+                let hex = chars.clone().take(4).collect::<String>();
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(decoded) if hex.len() == 4 => {
+                        text.push(decoded);
+                        chars.nth(3);
+                    }
+                    _ => text.push_str("\\u"),
+                }
+            }
+            Some(c @ ('"' | '\\' | '/')) => text.push(c),
+            Some(c) => {
+                text.push('\\');
+                text.push(c);
+            }
+            None => {}
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(json: &str) -> Vec<String> {
+        recover(json)
+            .into_iter()
+            .map(|block| block.content.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_recover_cut_off() {
+        let json = include_str!("fixtures/v2.json");
+        let cut = &json[..json.find("\"tags\"").unwrap()];
+        assert_eq!(texts(cut), ["2 MB * 3"]);
+
+        let cut = &json[..json.find(" * 3").unwrap()];
+        assert_eq!(texts(cut), ["2 MB"]);
+
+        let json = include_str!("fixtures/v1-text-only.json");
+        let cut = &json[..json.rfind("\"tags\"").unwrap()];
+        assert_eq!(texts(cut), ["First bold block", "A link"]);
+    }
+
+    #[test]
+    fn test_recover_bad_bytes() {
+        // notes that read after a bad byte was replaced are recovered with their formatting
+        let json = String::from_utf8_lossy(
+            b"{\"version\": 2, \"blocks\": [{\"level\": 1, \"text\": \"caf\xE9\", \"tags\": []}]}",
+        );
+        let blocks = recover(&json);
+        assert_eq!(blocks[0].level, 1);
+        assert_eq!(blocks[0].content.text, "caf\u{FFFD}");
+
+        let json = r#"{"version": 2, "blocks": [
+            {"text": "first\nline", "tags": [{"start": 0, "end": 1, "name": "bold"}},
+            {"text": "bad \q escape é \uZZ", "tags": []},
+            {"text": "", "tags": []},
+            {"text": "says \"text\": \"no block\"", "tags": []},
+            {"text": 12, "tags": []},
+        ]}"#;
+        assert_eq!(
+            texts(json),
+            [
+                "first\nline",
+                "bad \\q escape é \\uZZ",
+                "says \"text\": \"no block\""
+            ]
+        );
+
+        assert!(texts("").is_empty());
+        assert!(texts("\u{0}\u{0}\u{0}").is_empty());
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        std::fs::write(&path, "[{\"text\": ").unwrap();
+
+        let corrupt_path = quarantine(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(
+            std::fs::read_to_string(&corrupt_path).unwrap(),
+            "[{\"text\": "
+        );
+
+        let name = corrupt_path.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("notes.json."), "{}", name);
+        assert!(name.ends_with(".corrupt"), "{}", name);
+        assert_eq!(corrupt_path.parent(), Some(dir.path()));
+
+        assert!(quarantine(&path).is_err());
+    }
+
+    #[test]
+    fn test_quarantine_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        std::fs::write(&path, "[{\"text\": ").unwrap();
+        std::fs::write(backup_path(&path), "[]").unwrap();
+
+        let corrupt_path = quarantine(&path).unwrap();
+        let name = corrupt_path.file_name().unwrap().to_string_lossy();
+        let backup_copy = dir
+            .path()
+            .join(name.replacen("notes.json", "notes.json.bak", 1));
+        assert_eq!(std::fs::read_to_string(backup_copy).unwrap(), "[]");
+        // kept in place for the next save
+        assert_eq!(std::fs::read_to_string(backup_path(&path)).unwrap(), "[]");
+    }
+}
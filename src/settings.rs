@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// User settings, read from `~/.config/illpad/settings.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Directory of the Logseq graph blocks are written into, `~` is the home directory.
+    pub logseq_graph: Option<PathBuf>,
+    /// Whether blocks written into a journal page are removed from the pad.
+    pub clear_after_journal_export: bool,
+}
+
+impl Settings {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("illpad/settings.json"))
+    }
+
+    /// Loads the settings, falling back to the defaults when there are none or they can not
+    /// be read.
+    pub fn load() -> Self {
+        let Some(path) = Self::path().filter(|path| path.exists()) else {
+            return Self::default();
+        };
+
+        let settings = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|err| err.to_string()));
+        settings.unwrap_or_else(|err| {
+            warn!("Failed to read {}: {}", path.display(), err);
+            Self::default()
+        })
+    }
+
+    pub fn logseq_graph(&self) -> Option<PathBuf> {
+        let graph = self.logseq_graph.as_ref()?;
+        match graph.strip_prefix("~") {
+            Ok(relative) => dirs::home_dir().map(|home| home.join(relative)),
+            Err(_) => Some(graph.clone()),
+        }
+    }
+}
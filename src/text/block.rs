@@ -0,0 +1,320 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    hash::{BuildHasher, Hasher},
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::TextWithTags;
+
+/// A block of the pad. Blocks form an outline like in Logseq: a block is a child of the closest
+/// block above it with a lower level.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    /// Stays the same while the block is edited or moved.
+    #[serde(default = "new_id")]
+    pub id: String,
+    /// Saved as RFC 3339 in UTC, blocks can be sorted by it with `sort_siblings`. Notes saved
+    /// before blocks had times have the Unix epoch until `date_undated`.
+    #[serde(default)]
+    pub created: DateTime<Utc>,
+    #[serde(default)]
+    pub modified: DateTime<Utc>,
+    /// Indentation level, 0 for top level blocks.
+    #[serde(default)]
+    pub level: usize,
+    #[serde(default)]
+    pub mode: BlockMode,
+    #[serde(flatten)]
+    pub content: TextWithTags,
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: new_id(),
+            created: now,
+            modified: now,
+            level: 0,
+            mode: BlockMode::default(),
+            content: TextWithTags::default(),
+        }
+    }
+}
+
+impl Block {
+    /// Gives blocks saved without times the time of `time`, e.g. when the notes were saved.
+    pub fn date_undated(&mut self, time: DateTime<Utc>) {
+        if self.created == DateTime::<Utc>::UNIX_EPOCH {
+            self.created = time;
+        }
+        if self.modified == DateTime::<Utc>::UNIX_EPOCH {
+            self.modified = time;
+        }
+    }
+}
+
+/// A new block id, the creation time in milliseconds followed by random bits.
+pub fn new_id() -> String {
+    let random = RandomState::new().build_hasher().finish();
+    format!(
+        "{:011x}{:08x}",
+        Utc::now().timestamp_millis(),
+        random as u32
+    )
+}
+
+/// The hint of when a block was last edited, e.g. `edited 2h ago`.
+pub fn edited_ago<Tz: TimeZone>(modified: DateTime<Tz>, now: DateTime<Tz>) -> String
+where
+    Tz::Offset: Display,
+{
+    let minutes = (now - modified.clone()).num_minutes();
+    match minutes {
+        ..1 => String::from("edited just now"),
+        1..60 => format!("edited {}m ago", minutes),
+        60..1440 => format!("edited {}h ago", minutes / 60),
+        1440..10080 => format!("edited {}d ago", minutes / 1440),
+        _ => format!("edited on {}", modified.format("%Y-%m-%d")),
+    }
+}
+
+impl From<TextWithTags> for Block {
+    fn from(content: TextWithTags) -> Self {
+        Self {
+            content,
+            ..Default::default()
+        }
+    }
+}
+
+/// What a block holds. Text blocks are rich text copied as Markdown, the other modes are
+/// source text that is highlighted and copied as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockMode {
+    #[default]
+    Text,
+    Markdown,
+    Rust,
+    Json,
+    Shell,
+    Sql,
+    /// Every line is evaluated as an expression, e.g. `2 MB * 3`.
+    Math,
+}
+
+impl BlockMode {
+    /// Modes in the order `cycle` goes through them.
+    pub const ALL: [BlockMode; 7] = [
+        BlockMode::Text,
+        BlockMode::Markdown,
+        BlockMode::Rust,
+        BlockMode::Json,
+        BlockMode::Shell,
+        BlockMode::Sql,
+        BlockMode::Math,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlockMode::Text => "text",
+            BlockMode::Markdown => "markdown",
+            BlockMode::Rust => "rust",
+            BlockMode::Json => "json",
+            BlockMode::Shell => "shell",
+            BlockMode::Sql => "sql",
+            BlockMode::Math => "math",
+        }
+    }
+
+    /// Whether the block is code, drawn in a monospace font.
+    pub fn is_code(&self) -> bool {
+        !matches!(self, BlockMode::Text | BlockMode::Markdown)
+    }
+
+    pub fn cycle(&self) -> BlockMode {
+        let index = Self::ALL.iter().position(|mode| mode == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Lowers levels that skip one, e.g. after the parent of a block was deleted, so that every
+/// block is at most one level below the block above it.
+pub fn fix_levels(levels: &mut [usize]) {
+    let mut max = 0;
+    for level in levels.iter_mut() {
+        *level = (*level).min(max);
+        max = *level + 1;
+    }
+}
+
+/// The blocks that move with the block at `index` when it is indented or outdented: the block
+/// and its children. `None` when the block can not be moved that way, a block can only be
+/// indented under a block above it and can not be outdented from the top level.
+pub fn subtree_to_shift(
+    levels: &[usize],
+    index: usize,
+    outdent: bool,
+) -> Option<std::ops::Range<usize>> {
+    let level = *levels.get(index)?;
+    let movable = if outdent {
+        level > 0
+    } else {
+        index > 0 && levels[index - 1] >= level
+    };
+    if !movable {
+        return None;
+    }
+
+    let end = (index + 1..levels.len())
+        .find(|&i| levels[i] <= level)
+        .unwrap_or(levels.len());
+    Some(index..end)
+}
+
+/// The order of the blocks after sorting the block at `index` and its siblings, the blocks
+/// under the same parent, by `key` of their indices. Every sibling moves with its children,
+/// which keep their order, so levels fixed by `fix_levels` stay fixed.
+pub fn sort_siblings<K: Ord>(
+    levels: &[usize],
+    index: usize,
+    key: impl Fn(usize) -> K,
+) -> Vec<usize> {
+    let mut order = (0..levels.len()).collect::<Vec<_>>();
+    let Some(&level) = levels.get(index) else {
+        return order;
+    };
+    let start = (0..index)
+        .rev()
+        .find(|&i| levels[i] < level)
+        .map_or(0, |parent| parent + 1);
+    let end = (index + 1..levels.len())
+        .find(|&i| levels[i] < level)
+        .unwrap_or(levels.len());
+
+    let mut subtrees: Vec<Vec<usize>> = vec![];
+    for (i, &block_level) in levels.iter().enumerate().take(end).skip(start) {
+        match subtrees.last_mut() {
+            Some(subtree) if block_level > level => subtree.push(i),
+            _ => subtrees.push(vec![i]),
+        }
+    }
+    subtrees.sort_by_key(|subtree| key(subtree[0]));
+
+    order.splice(start..end, subtrees.into_iter().flatten());
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subtree_to_shift() {
+        let levels = [0, 1, 1, 2, 3, 1, 0];
+
+        assert_eq!(subtree_to_shift(&levels, 0, false), None);
+        assert_eq!(subtree_to_shift(&levels, 0, true), None);
+        assert_eq!(subtree_to_shift(&levels, 1, false), None);
+        assert_eq!(subtree_to_shift(&levels, 2, false), Some(2..5));
+        assert_eq!(subtree_to_shift(&levels, 2, true), Some(2..5));
+        assert_eq!(subtree_to_shift(&levels, 4, false), None);
+        assert_eq!(subtree_to_shift(&levels, 6, false), Some(6..7));
+        assert_eq!(subtree_to_shift(&levels, 7, false), None);
+    }
+
+    #[test]
+    fn test_fix_levels() {
+        let mut levels = [2, 3, 1, 3, 0, 1];
+        fix_levels(&mut levels);
+        assert_eq!(levels, [0, 1, 1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn test_sort_siblings() {
+        //  0 a        created 3
+        //  1   b      created 2
+        //  2     c
+        //  3   d      created 1
+        //  4 e
+        let levels = [0, 1, 2, 1, 0];
+        let created = [3, 2, 9, 1, 0];
+
+        assert_eq!(sort_siblings(&levels, 1, |i| created[i]), [0, 3, 1, 2, 4]);
+        assert_eq!(sort_siblings(&levels, 3, |i| created[i]), [0, 3, 1, 2, 4]);
+        // newest first
+        assert_eq!(
+            sort_siblings(&levels, 1, |i| std::cmp::Reverse(created[i])),
+            [0, 1, 2, 3, 4]
+        );
+        assert_eq!(sort_siblings(&levels, 0, |i| created[i]), [4, 0, 1, 2, 3]);
+        assert_eq!(sort_siblings(&levels, 2, |i| created[i]), [0, 1, 2, 3, 4]);
+        assert_eq!(sort_siblings(&levels, 5, |i| created[i]), [0, 1, 2, 3, 4]);
+
+        // the sorted levels are still valid
+        for index in 0..levels.len() {
+            let sorted = sort_siblings(&levels, index, |i| created[i])
+                .into_iter()
+                .map(|i| levels[i])
+                .collect::<Vec<_>>();
+            let mut fixed = sorted.clone();
+            fix_levels(&mut fixed);
+            assert_eq!(sorted, fixed);
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        // notes saved before blocks had levels, ids and times
+        let mut blocks: Vec<Block> = serde_json::from_str(
+            r#"[{"text": "a", "tags": []}, {"level": 1, "text": "b", "tags": []}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|b| (b.level, b.content.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0, "a"), (1, "b")]
+        );
+        assert_ne!(blocks[0].id, blocks[1].id);
+
+        let saved = Utc.with_ymd_and_hms(2025, 3, 7, 9, 30, 0).unwrap();
+        blocks[1].date_undated(saved);
+        blocks[1].id = String::from("id");
+        assert_eq!(
+            serde_json::to_string(&blocks[1]).unwrap(),
+            r#"{"id":"id","created":"2025-03-07T09:30:00Z","modified":"2025-03-07T09:30:00Z","level":1,"mode":"text","text":"b","tags":[]}"#
+        );
+
+        let block: Block =
+            serde_json::from_str(&serde_json::to_string(&blocks[1]).unwrap()).unwrap();
+        assert_eq!((block.id.as_str(), block.created), ("id", saved));
+    }
+
+    #[test]
+    fn test_new_id() {
+        let ids = (0..100)
+            .map(|_| new_id())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(ids.len(), 100);
+    }
+
+    #[test]
+    fn test_edited_ago() {
+        let now = Utc.with_ymd_and_hms(2025, 3, 7, 12, 0, 0).unwrap();
+        let ago = |minutes| edited_ago(now - chrono::Duration::minutes(minutes), now);
+
+        assert_eq!(ago(0), "edited just now");
+        // a clock that went back
+        assert_eq!(ago(-5), "edited just now");
+        assert_eq!(ago(5), "edited 5m ago");
+        assert_eq!(ago(150), "edited 2h ago");
+        assert_eq!(ago(3 * 1440), "edited 3d ago");
+        assert_eq!(ago(30 * 1440), "edited on 2025-02-05");
+    }
+}
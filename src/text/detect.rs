@@ -0,0 +1,388 @@
+use super::BlockMode;
+
+/// Score a mode needs to be detected, below it a text is plain text.
+const MIN_SCORE: usize = 2;
+
+/// Starts of lines that are likely a shell command.
+const SHELL_COMMANDS: &[&str] = &[
+    "$ ",
+    "#!/bin/",
+    "#!/usr/bin/env ",
+    "sudo ",
+    "cd ",
+    "ls ",
+    "echo ",
+    "export ",
+    "git ",
+    "cargo ",
+    "npm ",
+    "yarn ",
+    "apt ",
+    "brew ",
+    "docker ",
+    "kubectl ",
+    "curl ",
+    "mkdir ",
+    "rm ",
+    "cp ",
+    "mv ",
+    "cat ",
+    "grep ",
+    "chmod ",
+    "ssh ",
+    "pip ",
+    "make ",
+    "source ",
+];
+
+const SHELL_FEATURES: &[&str] = &[
+    " | ", " && ", " || ", "$(", "${", " > /", " 2>&1", "; then", "; do",
+];
+
+/// Words of Rust code, matched as whole words.
+const RUST_KEYWORDS: &[&str] = &[
+    "fn", "let", "mut", "impl", "pub", "use", "struct", "enum", "match", "trait", "mod", "self",
+    "Self", "Some", "None", "Ok", "Err", "String", "Vec", "usize", "i32", "u8", "str",
+];
+
+/// Punctuation of Rust code.
+const RUST_SYMBOLS: &[&str] = &[
+    "::",
+    "->",
+    "=>",
+    "&self",
+    "#[",
+    "!(",
+    "![",
+    ".unwrap()",
+    "&str",
+];
+
+/// Starts of Rust items, after an optional `pub`.
+const RUST_ITEMS: &[&str] = &[
+    "fn", "impl", "struct", "enum", "trait", "mod", "use", "const", "static", "type",
+];
+
+/// Phrases of SQL statements, matched case-insensitively as whole words.
+const SQL_FEATURES: &[&str] = &[
+    "select",
+    "from",
+    "where",
+    "insert into",
+    "values",
+    "update",
+    "set",
+    "delete from",
+    "create table",
+    "alter table",
+    "drop table",
+    "join",
+    "group by",
+    "order by",
+    "limit",
+    "primary key",
+    "not null",
+];
+
+/// Guesses the mode of a pasted text with a few heuristics, plain text when nothing stands
+/// out. Every mode needs lines shaped like its code, a keyword in a sentence is not enough.
+/// Markdown is only guessed from its layout, text mode already reads inline formatting.
+pub fn detect_mode(text: &str) -> BlockMode {
+    let text = text.trim();
+    if text.is_empty() {
+        return BlockMode::Text;
+    }
+
+    if (text.starts_with('{') || text.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+    {
+        return BlockMode::Json;
+    }
+
+    if is_markdown(text) {
+        return BlockMode::Markdown;
+    }
+    if is_prose(text) {
+        return BlockMode::Text;
+    }
+
+    let scores = [
+        (BlockMode::Rust, rust_score(text)),
+        (BlockMode::Shell, shell_score(text)),
+        (BlockMode::Sql, sql_score(text)),
+    ];
+    // `max_by_key` returns the last maximum, so the first mode of the list wins a tie
+    let (mode, score) = scores
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, score)| *score)
+        .unwrap();
+
+    if score >= MIN_SCORE {
+        mode
+    } else {
+        BlockMode::Text
+    }
+}
+
+/// The words of a text, split at everything that can not be part of an identifier.
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+}
+
+fn count(text: &str, features: &[&str]) -> usize {
+    features
+        .iter()
+        .map(|feature| text.matches(feature).count())
+        .sum()
+}
+
+fn rust_score(text: &str) -> usize {
+    let lines = text.lines().filter(|line| is_rust_line(line)).count();
+    let keywords = words(text)
+        .filter(|word| RUST_KEYWORDS.contains(word))
+        .count();
+    let symbols = count(text, RUST_SYMBOLS);
+    // a statement alone, like `f(x);`, could as well be another language
+    if lines == 0 || keywords + symbols == 0 {
+        return 0;
+    }
+    lines * 2 + keywords + symbols
+}
+
+/// Whether a line is shaped like Rust: an item header, a `let` statement, an attribute, a
+/// match arm or a line ending a statement or block.
+fn is_rust_line(line: &str) -> bool {
+    let line = line.trim();
+    let item = line
+        .strip_prefix("pub(crate) ")
+        .or_else(|| line.strip_prefix("pub "))
+        .unwrap_or(line);
+    let first_word = item.split_whitespace().next().unwrap_or_default();
+
+    line.starts_with("#[")
+        || line.starts_with("#![")
+        || (RUST_ITEMS.contains(&first_word) && line.ends_with(['{', ';']))
+        || (first_word == "let" && line.contains(" = ") && line.ends_with(';'))
+        || (line.contains(" => ") && line.ends_with([',', '{']))
+        || (line.ends_with(';') && line.contains('(') && line.contains(')'))
+        || line.ends_with('{')
+        || matches!(line, "}" | "};" | "},")
+}
+
+fn shell_score(text: &str) -> usize {
+    let lines = text.lines().map(str::trim_start);
+    let commands = lines
+        .clone()
+        .filter(|line| {
+            SHELL_COMMANDS
+                .iter()
+                .any(|command| line.starts_with(command) || *line == command.trim_end())
+        })
+        .count();
+    // flags and pipes are only counted around commands
+    if commands == 0 {
+        return 0;
+    }
+    let flags = text
+        .split_whitespace()
+        .filter(|word| {
+            word.strip_prefix('-')
+                .is_some_and(|flag| flag.starts_with(|c: char| c.is_ascii_alphabetic() || c == '-'))
+        })
+        .count();
+    let prompts = lines
+        .filter(|line| line.starts_with("$ ") || line.starts_with("#!"))
+        .count();
+    let features = count(text, SHELL_FEATURES);
+    // a sentence can start with a command, e.g. "make sure to call Anna"
+    if commands < 2 && flags + prompts + features == 0 {
+        return 0;
+    }
+    commands * 2 + flags.min(2) + features
+}
+
+fn sql_score(text: &str) -> usize {
+    let lowercase = text.to_lowercase();
+    // the words of the text between spaces, so that phrases are matched as whole words
+    let spaced = format!(" {} ", words(&lowercase).collect::<Vec<_>>().join(" "));
+    let has = |phrase: &str| spaced.contains(&format!(" {} ", phrase));
+
+    // a statement starts with a keyword and has the clause that keyword needs
+    let statement = match words(&lowercase).next().unwrap_or_default() {
+        "select" => has("from"),
+        "insert" => has("insert into"),
+        "update" => has("set"),
+        "delete" => has("delete from"),
+        "create" | "alter" | "drop" => has("table") || has("index") || has("view"),
+        "with" => lowercase.contains(" as ("),
+        _ => false,
+    };
+    if !statement {
+        return 0;
+    }
+    SQL_FEATURES.iter().filter(|phrase| has(phrase)).count() + 1
+}
+
+/// Whether a text is laid out as a Markdown document, with a fenced code block, a heading, a
+/// table or a quote, even when it holds code. `# ` and `> ` only count when no line is a shell
+/// command, as they also start comments and redirections.
+fn is_markdown(text: &str) -> bool {
+    let mut lines = text.lines().map(str::trim_start);
+    let heading = |line: &str| {
+        let level = line.len() - line.trim_start_matches('#').len();
+        (2..=6).contains(&level) && line[level..].starts_with(' ')
+    };
+    // the line under the header of a table, e.g. `| --- | :-: |`
+    let table_rule = |line: &str| {
+        line.starts_with('|') && line.contains('-') && line.chars().all(|c| "|-: ".contains(c))
+    };
+
+    lines
+        .clone()
+        .any(|line| line.starts_with("```") || heading(line) || table_rule(line))
+        || (shell_score(text) == 0
+            && lines.any(|line| line.starts_with("# ") || line.starts_with("> ")))
+}
+
+/// Whether most lines of a text end like a sentence, which code rarely does.
+fn is_prose(text: &str) -> bool {
+    let lines = text.lines().filter(|line| !line.trim().is_empty());
+    let (count, sentences) = lines.fold((0, 0), |(count, sentences), line| {
+        let last_word = line.split_whitespace().next_back().unwrap_or_default();
+        let sentence = last_word.len() > 2
+            && last_word.ends_with(['.', '?', '!'])
+            && last_word
+                .chars()
+                .all(|c| c.is_alphabetic() || ".,?!'\"".contains(c));
+        (count + 1, sentences + sentence as usize)
+    });
+    sentences * 2 >= count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Snippets as they are pasted, labeled with the mode they should be detected as.
+    const CORPUS: &[(BlockMode, &str)] = &[
+        (
+            BlockMode::Json,
+            r#"{"name": "illpad", "version": 1, "tags": ["a", "b"]}"#,
+        ),
+        (BlockMode::Json, "[\n  1,\n  2,\n  3\n]"),
+        (
+            BlockMode::Json,
+            "{\n  \"compilerOptions\": {\n    \"strict\": true,\n    \"target\": null\n  }\n}",
+        ),
+        (
+            BlockMode::Rust,
+            "fn main() {\n    let x = vec![1, 2, 3];\n    println!(\"{:?}\", x);\n}",
+        ),
+        (
+            BlockMode::Rust,
+            "impl Default for Settings {\n    fn default() -> Self {\n        Self { path: None }\n    }\n}",
+        ),
+        (BlockMode::Rust, "let blocks = self.get_blocks().unwrap();"),
+        (
+            BlockMode::Rust,
+            "#[derive(Debug, Clone)]\npub struct Block {\n    pub level: usize,\n}",
+        ),
+        (
+            BlockMode::Rust,
+            "match key {\n    Some(key) => key.len(),\n    None => 0,\n}",
+        ),
+        (BlockMode::Shell, "cargo build --release"),
+        (BlockMode::Shell, "$ git log --oneline | head -5"),
+        (
+            BlockMode::Shell,
+            "#!/bin/bash\nfor f in *.md; do\n  echo \"$f\"\ndone",
+        ),
+        (
+            BlockMode::Shell,
+            "sudo apt install libgtk-4-dev && cargo install --path .",
+        ),
+        (
+            BlockMode::Shell,
+            "docker run -it --rm -v $(pwd):/work ubuntu bash",
+        ),
+        (
+            BlockMode::Sql,
+            "SELECT id, name FROM users WHERE created_at > now() - interval '1 day';",
+        ),
+        (
+            BlockMode::Sql,
+            "insert into notes (title, body) values ('a', 'b');",
+        ),
+        (
+            BlockMode::Sql,
+            "CREATE TABLE blocks (\n  id INTEGER PRIMARY KEY,\n  text TEXT NOT NULL\n);",
+        ),
+        (
+            BlockMode::Sql,
+            "select count(*)\nfrom orders o\njoin users u on u.id = o.user_id\ngroup by u.id",
+        ),
+        (
+            BlockMode::Markdown,
+            "# Meeting notes\n\n- agreed on the **release date**\n- see [the plan](https://example.com)",
+        ),
+        (
+            BlockMode::Markdown,
+            "## Setup\n\n```\ncargo run\n```\n\n> works on Linux only",
+        ),
+        (
+            BlockMode::Markdown,
+            "| name | size |\n| --- | --- |\n| a | 1 |",
+        ),
+        (
+            BlockMode::Markdown,
+            "Run it with:\n\n```rust\nfn main() {\n    let x = 1;\n}\n```",
+        ),
+        (
+            BlockMode::Shell,
+            "# install the dependencies\nsudo apt install -y libgtk-4-dev",
+        ),
+        // inline Markdown is left to text mode, which reads its formatting
+        (BlockMode::Text, "**Bold** and **more**"),
+        (BlockMode::Text, "see [docs](https://a.b) and **important**"),
+        // prose with words of code
+        (BlockMode::Text, "Buy milk and eggs"),
+        (BlockMode::Text, "I use it because the outlet is broken."),
+        (BlockMode::Text, "None of us could make it; let me know."),
+        (
+            BlockMode::Text,
+            "Meeting notes: discuss the String type and the enum values.",
+        ),
+        (
+            BlockMode::Text,
+            "Select the best option from the list where it fits.",
+        ),
+        (
+            BlockMode::Text,
+            "Fix the match on Some(key) first\nthen let Anna review it",
+        ),
+        (
+            BlockMode::Text,
+            "We should select a date for the release from the list, let me know what works.",
+        ),
+        (
+            BlockMode::Text,
+            "Call Anna about the trip. She wants to leave early, maybe on Friday?",
+        ),
+        (BlockMode::Text, "make sure to call Anna"),
+        (BlockMode::Text, "cat pictures from Sam"),
+        (BlockMode::Text, "source of the bug is the parser"),
+        (BlockMode::Text, "- eggs\n- milk\n- bread"),
+        (BlockMode::Text, "https://example.com/some/page"),
+        (BlockMode::Text, "{not json}"),
+        (BlockMode::Text, ""),
+    ];
+
+    #[test]
+    fn test_detect_mode() {
+        for (mode, text) in CORPUS {
+            assert_eq!(detect_mode(text), *mode, "{}", text);
+        }
+    }
+}
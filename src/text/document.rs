@@ -0,0 +1,315 @@
+use super::{HIGHLIGHT_COLOURS, Tag, TextWithTags, text_buffer::normalize_tags};
+
+/// Text with tag ranges that keeps the ranges in place while the text is edited, the way a
+/// `gtk::TextBuffer` does, but without GTK. Offsets are in chars, as in `Tag`.
+///
+/// Tags with the same name and attributes never overlap or touch, applying one over another
+/// merges them, like applying a `TextTag` twice does in a buffer.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    text: String,
+    tags: Vec<Tag>,
+}
+
+impl Document {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            tags: vec![],
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    /// Length of the text in chars.
+    pub fn char_count(&self) -> i32 {
+        self.text.chars().count() as i32
+    }
+
+    fn clamp(&self, offset: i32) -> i32 {
+        offset.clamp(0, self.char_count())
+    }
+
+    fn byte_offset(&self, offset: i32) -> usize {
+        self.text
+            .char_indices()
+            .nth(offset as usize)
+            .map_or(self.text.len(), |(index, _)| index)
+    }
+
+    /// Inserts text. Like in a buffer, text inserted inside or right at the end of a tag is
+    /// covered by it (typing continues the tag before the cursor), text inserted at the start
+    /// of a tag is not.
+    pub fn insert(&mut self, offset: i32, text: &str) {
+        let offset = self.clamp(offset);
+        let len = text.chars().count() as i32;
+
+        let index = self.byte_offset(offset);
+        self.text.insert_str(index, text);
+
+        for tag in self.tags.iter_mut() {
+            if offset <= tag.start {
+                tag.start += len;
+                tag.end += len;
+            } else if offset <= tag.end {
+                tag.end += len;
+            }
+        }
+    }
+
+    /// Inserts text with exactly its own tags, e.g. pasted Markdown.
+    pub fn insert_with_tags(&mut self, offset: i32, text_with_tags: &TextWithTags) {
+        let offset = self.clamp(offset);
+        let end = offset + text_with_tags.text.chars().count() as i32;
+        self.insert(offset, &text_with_tags.text);
+
+        let names = self
+            .tags
+            .iter()
+            .map(|tag| tag.name.clone())
+            .collect::<std::collections::BTreeSet<_>>();
+        for name in names {
+            self.remove_tags_named(&name, offset, end);
+        }
+
+        for tag in text_with_tags.tags.iter() {
+            self.apply_tag(Tag {
+                start: tag.start + offset,
+                end: tag.end + offset,
+                ..tag.clone()
+            });
+        }
+    }
+
+    /// Deletes the text between `start` and `end`, tags inside of it shrink or disappear.
+    pub fn delete(&mut self, start: i32, end: i32) {
+        let (start, end) = (self.clamp(start), self.clamp(end));
+        if start >= end {
+            return;
+        }
+
+        let (from, to) = (self.byte_offset(start), self.byte_offset(end));
+        self.text.replace_range(from..to, "");
+
+        let shift = |offset: i32| {
+            if offset <= start {
+                offset
+            } else if offset >= end {
+                offset - (end - start)
+            } else {
+                start
+            }
+        };
+
+        for tag in self.tags.iter_mut() {
+            tag.start = shift(tag.start);
+            tag.end = shift(tag.end);
+        }
+        self.tags.retain(|tag| tag.start < tag.end);
+        self.merge_tags();
+    }
+
+    /// Applies a tag over its range, merging it with the same tag it overlaps or touches. A
+    /// highlight replaces highlights of other colours in its range and a link other links, e.g.
+    /// when a new URL is pasted over a part of a link.
+    pub fn apply_tag(&mut self, mut tag: Tag) {
+        tag.start = self.clamp(tag.start);
+        tag.end = self.clamp(tag.end);
+        if tag.start >= tag.end {
+            return;
+        }
+
+        if tag.is_highlight() {
+            for colour in HIGHLIGHT_COLOURS.iter().filter(|c| c.name != tag.name) {
+                self.remove_tags_named(colour.name, tag.start, tag.end);
+            }
+        }
+        if tag.name == "link" {
+            self.remove_tags_named("link", tag.start, tag.end);
+        }
+
+        self.tags.push(tag);
+        self.merge_tags();
+    }
+
+    /// Removes the tag (by name and attributes) from its range, splitting it when needed.
+    pub fn remove_tag(&mut self, tag: &Tag) {
+        let mut tags = vec![];
+
+        for t in std::mem::take(&mut self.tags) {
+            if t.name != tag.name || t.attrs != tag.attrs {
+                tags.push(t);
+                continue;
+            }
+
+            if t.start < tag.start {
+                tags.push(Tag {
+                    end: t.end.min(tag.start),
+                    ..t.clone()
+                });
+            }
+            if t.end > tag.end {
+                tags.push(Tag {
+                    start: t.start.max(tag.end),
+                    ..t
+                });
+            }
+        }
+
+        self.tags = tags;
+    }
+
+    /// Removes every tag named `name` between `start` and `end`, whatever its attributes.
+    pub fn remove_tags_named(&mut self, name: &str, start: i32, end: i32) {
+        let tags = self
+            .tags
+            .iter()
+            .filter(|tag| tag.name == name)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for tag in tags {
+            self.remove_tag(&Tag { start, end, ..tag });
+        }
+    }
+
+    fn merge_tags(&mut self) {
+        normalize_tags(&mut self.tags);
+    }
+}
+
+impl From<TextWithTags> for Document {
+    fn from(text_with_tags: TextWithTags) -> Self {
+        let mut document = Document::new(&text_with_tags.text);
+        for tag in text_with_tags.tags {
+            document.apply_tag(tag);
+        }
+        document
+    }
+}
+
+impl From<Document> for TextWithTags {
+    fn from(document: Document) -> Self {
+        TextWithTags {
+            text: document.text,
+            tags: document.tags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(start: i32, end: i32, name: &str) -> Tag {
+        Tag {
+            start,
+            end,
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn tag_tuples(document: &Document) -> Vec<(i32, i32, &str)> {
+        document
+            .tags()
+            .iter()
+            .map(|t| (t.start, t.end, t.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut document = Document::new("one two");
+        document.apply_tag(tag(4, 7, "bold"));
+
+        // at the start of a tag
+        document.insert(4, "글 ");
+        assert_eq!(document.text(), "one 글 two");
+        assert_eq!(tag_tuples(&document), vec![(6, 9, "bold")]);
+
+        // inside of a tag
+        document.insert(7, "w");
+        assert_eq!(document.text(), "one 글 twwo");
+        assert_eq!(tag_tuples(&document), vec![(6, 10, "bold")]);
+
+        // at the end of a tag
+        document.insert(10, "!");
+        assert_eq!(document.text(), "one 글 twwo!");
+        assert_eq!(tag_tuples(&document), vec![(6, 11, "bold")]);
+
+        // pasted text only has its own tags
+        document.insert_with_tags(11, &TextWithTags::from_markdown(" ==a=="));
+        assert_eq!(document.text(), "one 글 twwo! a");
+        assert_eq!(
+            tag_tuples(&document),
+            vec![(6, 11, "bold"), (12, 13, "highlight")]
+        );
+
+        document.insert_with_tags(0, &TextWithTags::from_markdown("**a** "));
+        assert_eq!(document.text(), "a one 글 twwo! a");
+        assert_eq!(
+            tag_tuples(&document),
+            vec![(0, 1, "bold"), (8, 13, "bold"), (14, 15, "highlight")]
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut document = Document::new("one two three");
+        document.apply_tag(tag(0, 3, "bold"));
+        document.apply_tag(tag(4, 7, "italic"));
+        document.apply_tag(tag(8, 13, "bold"));
+
+        document.delete(2, 9);
+        assert_eq!(document.text(), "onhree");
+        assert_eq!(tag_tuples(&document), vec![(0, 6, "bold")]);
+
+        document.delete(0, 6);
+        assert_eq!(document.char_count(), 0);
+        assert!(document.tags().is_empty());
+    }
+
+    #[test]
+    fn test_apply_and_remove_tag() {
+        let mut document = Document::new("abcdefgh");
+        document.apply_tag(tag(0, 3, "bold"));
+        document.apply_tag(tag(2, 5, "bold"));
+        document.apply_tag(tag(5, 6, "bold"));
+        document.apply_tag(tag(6, 20, "code"));
+        assert_eq!(tag_tuples(&document), vec![(0, 6, "bold"), (6, 8, "code")]);
+
+        document.remove_tag(&tag(2, 4, "bold"));
+        assert_eq!(
+            tag_tuples(&document),
+            vec![(0, 2, "bold"), (4, 6, "bold"), (6, 8, "code")]
+        );
+
+        document.apply_tag(Tag::link(0, 4, "https://a"));
+        // links are not nested, the newer one to b cuts the one to a
+        document.apply_tag(Tag::link(2, 8, "https://b"));
+        document.remove_tags_named("link", 3, 8);
+        assert_eq!(
+            document
+                .tags()
+                .iter()
+                .filter_map(|t| t.href().map(|href| (t.start, t.end, href)))
+                .collect::<Vec<_>>(),
+            vec![(0, 2, "https://a"), (2, 3, "https://b")]
+        );
+    }
+
+    #[test]
+    fn test_into_text_with_tags() {
+        let document = Document::from(TextWithTags::from_markdown("a **bold** ==move=="));
+        let text_with_tags: TextWithTags = document.into();
+        assert_eq!(text_with_tags.text, "a bold move");
+        assert_eq!(text_with_tags.clipboard_text(), "a **bold** ==move==");
+    }
+}
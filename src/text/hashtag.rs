@@ -0,0 +1,139 @@
+use super::BlockMode;
+
+/// Buffer tag drawing `#tag` and `#[[multi word]]` hashtags, which stay plain text in the block.
+pub const HASHTAG_TAG: &str = "hashtag";
+
+/// A hashtag of a text, `name` is what follows the `#`, without the brackets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hashtag {
+    pub start: i32,
+    pub end: i32,
+    pub name: String,
+}
+
+/// Finds the hashtags of a text. A hashtag starts with a `#` at the start of a word and ends
+/// at whitespace or at the punctuation ending a sentence, Markdown headings are not hashtags.
+pub fn hashtags(text: &str) -> Vec<Hashtag> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut tags = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let at_word_start = i == 0 || chars[i - 1].is_whitespace();
+        if chars[i] != '#' || !at_word_start {
+            i += 1;
+            continue;
+        }
+
+        let name_start = i + 1;
+        if chars[name_start..].starts_with(&['[', '[']) {
+            let end = (name_start + 2..chars.len())
+                .take_while(|&j| chars[j] != '\n')
+                .find(|&j| chars[j..].starts_with(&[']', ']']));
+            if let Some(end) = end.filter(|&end| end > name_start + 2) {
+                tags.push(Hashtag {
+                    start: i as i32,
+                    end: end as i32 + 2,
+                    name: chars[name_start + 2..end].iter().collect(),
+                });
+                i = end + 2;
+                continue;
+            }
+        }
+
+        let mut end = name_start;
+        while end < chars.len() && !chars[end].is_whitespace() {
+            end += 1;
+        }
+        while end > name_start
+            && matches!(
+                chars[end - 1],
+                '.' | ',' | ';' | ':' | '!' | '?' | ')' | '"' | '\''
+            )
+        {
+            end -= 1;
+        }
+        if end > name_start && !matches!(chars[name_start], '#' | '[') {
+            tags.push(Hashtag {
+                start: i as i32,
+                end: end as i32,
+                name: chars[name_start..end].iter().collect(),
+            });
+        }
+        i = end.max(i + 1);
+    }
+
+    tags
+}
+
+/// Whether a block has all of the given hashtags, compared case-insensitively. Code blocks have
+/// none, like they are drawn, e.g. a shell `#comment` is not a hashtag.
+pub fn has_hashtags(text: &str, mode: BlockMode, names: &[String]) -> bool {
+    if mode.is_code() {
+        return names.is_empty();
+    }
+    let found = hashtags(text)
+        .into_iter()
+        .map(|tag| tag.name.to_lowercase())
+        .collect::<Vec<_>>();
+    names
+        .iter()
+        .all(|name| found.contains(&name.trim_start_matches('#').to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(text: &str) -> Vec<String> {
+        hashtags(text).into_iter().map(|tag| tag.name).collect()
+    }
+
+    #[test]
+    fn test_hashtags() {
+        assert_eq!(
+            hashtags("Fix #bug, see #[[release notes]]"),
+            vec![
+                Hashtag {
+                    start: 4,
+                    end: 8,
+                    name: String::from("bug"),
+                },
+                Hashtag {
+                    start: 14,
+                    end: 32,
+                    name: String::from("release notes"),
+                },
+            ]
+        );
+        assert_eq!(names("#한글 #a/b #c."), vec!["한글", "a/b", "c"]);
+        assert_eq!(names("# Heading\n## Sub"), Vec::<String>::new());
+        assert_eq!(names("issue#12 #[[]] #[[open"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_has_hashtags() {
+        let text = "Call #Alice about #[[project x]]";
+        let tags = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert!(has_hashtags(text, BlockMode::Text, &tags(&[])));
+        assert!(has_hashtags(text, BlockMode::Text, &tags(&["alice"])));
+        assert!(has_hashtags(
+            text,
+            BlockMode::Text,
+            &tags(&["#alice", "Project X"])
+        ));
+        assert!(!has_hashtags(
+            text,
+            BlockMode::Text,
+            &tags(&["alice", "bob"])
+        ));
+
+        // hashtags are not drawn in code, so they are not filtered by either
+        let script = "ls # #alice";
+        assert!(has_hashtags(script, BlockMode::Text, &tags(&["alice"])));
+        assert!(!has_hashtags(script, BlockMode::Shell, &tags(&["alice"])));
+        assert!(has_hashtags(script, BlockMode::Shell, &tags(&[])));
+        assert!(has_hashtags("# a #b", BlockMode::Markdown, &tags(&["b"])));
+    }
+}
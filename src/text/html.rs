@@ -0,0 +1,620 @@
+use super::{Document, HIGHLIGHT_COLOURS, Tag, TextWithTags, is_url, text_buffer::normalize_tags};
+
+/// Elements whose content is never shown.
+const HIDDEN_ELEMENTS: &[&str] = &["head", "script", "style", "template", "title"];
+
+/// Elements that start on a new line.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "tr",
+    "ul",
+];
+
+/// Opening and closing HTML of a tag.
+fn html_element(tag: &Tag) -> Option<(String, &'static str)> {
+    let element = match tag.name.as_str() {
+        "bold" => (String::from("<b>"), "</b>"),
+        "italic" => (String::from("<i>"), "</i>"),
+        "underline" => (String::from("<u>"), "</u>"),
+        "strikethrough" => (String::from("<s>"), "</s>"),
+        "code" => (String::from("<code>"), "</code>"),
+        "highlight" => (String::from("<mark>"), "</mark>"),
+        "link" => (format!("<a href=\"{}\">", escape(tag.href()?)), "</a>"),
+        name => {
+            let colour = HIGHLIGHT_COLOURS.iter().find(|c| c.name == name)?;
+            (
+                format!("<mark style=\"background-color: {}\">", colour.background),
+                "</mark>",
+            )
+        }
+    };
+    Some(element)
+}
+
+fn escape(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\n' => html.push_str("<br>"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// An element start or end tag of the HTML being read.
+struct Element {
+    name: String,
+    closing: bool,
+    attrs: Vec<(String, String)>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether whitespace in the element is kept, e.g. in the `<div style="white-space: pre">`
+    /// that VS Code copies code in.
+    fn is_preformatted(&self) -> bool {
+        let style = self.attr("style").unwrap_or_default().replace(' ', "");
+        self.name == "pre" || style.to_lowercase().contains("white-space:pre")
+    }
+
+    /// The tag the element stands for, if it is one illpad has.
+    fn tag(&self) -> Option<Tag> {
+        let name = match self.name.as_str() {
+            "b" | "strong" => {
+                // Google Docs wraps everything it copies in a `<b style="font-weight:normal">`
+                let style = self.attr("style").unwrap_or_default().replace(' ', "");
+                if style.contains("font-weight:normal") {
+                    return None;
+                }
+                "bold"
+            }
+            "i" | "em" | "cite" => "italic",
+            "u" | "ins" => "underline",
+            "s" | "strike" | "del" => "strikethrough",
+            "code" | "kbd" | "samp" | "tt" => "code",
+            "mark" => {
+                let style = self.attr("style").unwrap_or_default().to_lowercase();
+                HIGHLIGHT_COLOURS
+                    .iter()
+                    .find(|c| {
+                        let colour = c.name.trim_start_matches("highlight-");
+                        style.contains(&c.background.to_lowercase()) || style.contains(colour)
+                    })
+                    .map_or("highlight", |c| c.name)
+            }
+            "a" => {
+                let href = self.attr("href").filter(|href| is_url(href))?;
+                return Some(Tag::link(0, 0, href));
+            }
+            _ => return None,
+        };
+
+        Some(Tag {
+            name: name.to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads the element at `offset`, which is a `<`, returning it with the offset after it.
+/// Comments and declarations are read as elements without a name.
+fn read_element(chars: &[char], offset: usize) -> Option<(Element, usize)> {
+    let rest = |from: usize, pattern: &str| {
+        let pattern = pattern.chars().collect::<Vec<_>>();
+        (from..chars.len()).find(|&i| chars[i..].starts_with(&pattern))
+    };
+    let skipped = |end: usize| {
+        let element = Element {
+            name: String::new(),
+            closing: false,
+            attrs: vec![],
+        };
+        (element, end)
+    };
+
+    let mut i = offset + 1;
+    match chars.get(i) {
+        Some('!') if chars[i..].starts_with(&['!', '-', '-']) => {
+            let end = rest(i + 3, "-->").map_or(chars.len(), |end| end + 3);
+            return Some(skipped(end));
+        }
+        Some('!' | '?') => return Some(skipped(rest(i, ">").map_or(chars.len(), |end| end + 1))),
+        _ => {}
+    }
+
+    let closing = chars.get(i) == Some(&'/');
+    if closing {
+        i += 1;
+    }
+    if !chars.get(i)?.is_ascii_alphabetic() {
+        return None;
+    }
+
+    let is_name = |c: &char| !c.is_whitespace() && !matches!(c, '/' | '>' | '=');
+    let read_name = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(is_name) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect::<String>().to_lowercase()
+    };
+    let skip_space = |i: &mut usize| {
+        while chars
+            .get(*i)
+            .is_some_and(|c| c.is_whitespace() || *c == '/')
+        {
+            *i += 1;
+        }
+    };
+
+    let name = read_name(&mut i);
+    let mut attrs = vec![];
+    loop {
+        skip_space(&mut i);
+        match chars.get(i) {
+            None => return None,
+            Some('>') => break,
+            _ => {}
+        }
+
+        let attr = read_name(&mut i);
+        if attr.is_empty() {
+            i += 1;
+            continue;
+        }
+        let mut value = String::new();
+        if chars.get(i) == Some(&'=') {
+            i += 1;
+            match chars.get(i) {
+                Some(&quote) if quote == '"' || quote == '\'' => {
+                    let end = (i + 1..chars.len()).find(|&j| chars[j] == quote)?;
+                    value = chars[i + 1..end].iter().collect();
+                    i = end + 1;
+                }
+                _ => {
+                    let start = i;
+                    while chars
+                        .get(i)
+                        .is_some_and(|c| !c.is_whitespace() && *c != '>')
+                    {
+                        i += 1;
+                    }
+                    value = chars[start..i].iter().collect();
+                }
+            }
+        }
+        attrs.push((attr, decode_entities(&value)));
+    }
+
+    let element = Element {
+        name,
+        closing,
+        attrs,
+    };
+    Some((element, i + 1))
+}
+
+/// Reads the character reference at `offset`, which is a `&`, returning the character with
+/// the offset after it.
+fn read_entity(chars: &[char], offset: usize) -> Option<(char, usize)> {
+    let end = (offset + 1..chars.len().min(offset + 12)).find(|&i| chars[i] == ';')?;
+    let name = chars[offset + 1..end].iter().collect::<String>();
+
+    let c = match name.as_str() {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        _ => {
+            let code = match name.strip_prefix('#')? {
+                hex if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16),
+                decimal => decimal.parse(),
+            };
+            char::from_u32(code.ok()?)?
+        }
+    };
+    Some((c, end + 1))
+}
+
+fn decode_entities(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut decoded = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match read_entity(&chars, i).filter(|_| chars[i] == '&') {
+            Some((c, next)) => {
+                decoded.push(c);
+                i = next;
+            }
+            None => {
+                decoded.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+impl TextWithTags {
+    /// Reads HTML, e.g. copied from a browser, keeping the formatting illpad has tags for and
+    /// list items as `- ` lines. Other markup is dropped, scripts and styles with their content.
+    pub fn from_html(html: &str) -> Self {
+        let chars = html.chars().collect::<Vec<_>>();
+        let mut text = String::new();
+        let mut len = 0;
+        let mut tags = vec![];
+        // open elements that carry a tag, with the tag started at the offset it opened
+        let mut open: Vec<(String, Tag)> = vec![];
+        // open lists, with the number of the next item of ordered ones
+        let mut lists: Vec<Option<usize>> = vec![];
+        let mut hidden: Option<String> = None;
+        // open elements that keep whitespace, with how many elements of their name are open in
+        // them
+        let mut preformatted: Vec<(String, usize)> = vec![];
+        let mut space = false;
+
+        let push = |text: &mut String, len: &mut i32, s: &str| {
+            text.push_str(s);
+            *len += s.chars().count() as i32;
+        };
+        let at_line_start = |text: &String| text.is_empty() || text.ends_with('\n');
+
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '<'
+                && let Some((element, next)) = read_element(&chars, i)
+            {
+                i = next;
+                let name = element.name.as_str();
+
+                if let Some(hidden_name) = &hidden {
+                    if element.closing && name == hidden_name {
+                        hidden = None;
+                    }
+                    continue;
+                }
+                if HIDDEN_ELEMENTS.contains(&name) && !element.closing {
+                    hidden = Some(element.name);
+                    continue;
+                }
+
+                if name == "br" {
+                    push(&mut text, &mut len, "\n");
+                    space = false;
+                    continue;
+                }
+
+                if element.closing {
+                    if let Some(position) = preformatted.iter().rposition(|(n, _)| n == name) {
+                        match &mut preformatted[position].1 {
+                            0 => {
+                                preformatted.remove(position);
+                            }
+                            nested => *nested -= 1,
+                        }
+                    }
+                } else if element.is_preformatted() {
+                    preformatted.push((element.name.clone(), 0));
+                } else if let Some((_, nested)) =
+                    preformatted.iter_mut().rev().find(|(n, _)| n == name)
+                {
+                    *nested += 1;
+                }
+
+                if BLOCK_ELEMENTS.contains(&name) {
+                    if !at_line_start(&text) {
+                        push(&mut text, &mut len, "\n");
+                    }
+                    space = false;
+
+                    match (name, element.closing) {
+                        ("ul", false) => lists.push(None),
+                        ("ol", false) => lists.push(Some(1)),
+                        ("ul" | "ol", true) => {
+                            lists.pop();
+                        }
+                        ("li", false) => {
+                            let indent = "  ".repeat(lists.len().saturating_sub(1));
+                            let bullet = match lists.last_mut() {
+                                Some(Some(number)) => {
+                                    *number += 1;
+                                    format!("{}. ", *number - 1)
+                                }
+                                _ => String::from("- "),
+                            };
+                            push(&mut text, &mut len, &format!("{}{}", indent, bullet));
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                if element.closing {
+                    if let Some(position) = open.iter().rposition(|(n, _)| n == name) {
+                        let (_, tag) = open.remove(position);
+                        tags.push(Tag { end: len, ..tag });
+                    }
+                } else if let Some(tag) = element.tag() {
+                    if space && !at_line_start(&text) {
+                        push(&mut text, &mut len, " ");
+                    }
+                    space = false;
+                    open.push((element.name, Tag { start: len, ..tag }));
+                }
+                continue;
+            }
+
+            let (c, next) = match chars[i] {
+                '&' => read_entity(&chars, i).unwrap_or(('&', i + 1)),
+                c => (c, i + 1),
+            };
+            i = next;
+
+            if hidden.is_some() {
+                continue;
+            }
+            if preformatted.is_empty() && c.is_whitespace() && chars[next - 1].is_whitespace() {
+                space = true;
+                continue;
+            }
+            // editors indent with `&nbsp;` so that the spaces are not collapsed
+            let c = if preformatted.is_empty() || c != '\u{A0}' {
+                c
+            } else {
+                ' '
+            };
+            if space && !at_line_start(&text) {
+                push(&mut text, &mut len, " ");
+            }
+            space = false;
+            push(&mut text, &mut len, &c.to_string());
+        }
+
+        for (_, tag) in open {
+            tags.push(Tag { end: len, ..tag });
+        }
+
+        let mut document = Document::new(&text);
+        for tag in tags {
+            document.apply_tag(tag);
+        }
+
+        // leading and trailing blank lines of the copied fragment, keeping the indentation of its
+        // first line
+        let trailing = text.chars().rev().take_while(|c| c.is_whitespace()).count() as i32;
+        document.delete(len - trailing, len);
+        let blank_lines = text
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .collect::<String>();
+        let leading = blank_lines
+            .rfind('\n')
+            .map_or(0, |end| blank_lines[..=end].chars().count()) as i32;
+        document.delete(0, leading);
+
+        document.into()
+    }
+
+    /// Renders the text as an HTML fragment for apps that do not read Markdown, e.g. email or
+    /// office tools. Overlapping tags are closed and reopened so elements are properly nested.
+    pub fn html_text(&self) -> String {
+        let chars = self.text.chars().collect::<Vec<_>>();
+        let len = chars.len() as i32;
+
+        let mut tags = self.tags.clone();
+        for tag in tags.iter_mut() {
+            tag.start = tag.start.clamp(0, len);
+            tag.end = tag.end.clamp(0, len);
+        }
+        normalize_tags(&mut tags);
+        tags.retain(|tag| html_element(tag).is_some());
+
+        let mut boundaries = tags
+            .iter()
+            .flat_map(|tag| [tag.start, tag.end])
+            .chain([0, len])
+            .collect::<Vec<_>>();
+        boundaries.sort();
+        boundaries.dedup();
+
+        let mut html = String::new();
+        // elements left open, the outermost first
+        let mut stack: Vec<&Tag> = vec![];
+
+        for window in boundaries.windows(2) {
+            let (start, end) = (window[0], window[1]);
+
+            let cut = stack
+                .iter()
+                .position(|tag| tag.end <= start)
+                .unwrap_or(stack.len());
+            for tag in stack[cut..].iter().rev() {
+                html.push_str(html_element(tag).unwrap().1);
+            }
+
+            let mut opening = stack
+                .split_off(cut)
+                .into_iter()
+                .filter(|tag| tag.end > start)
+                .chain(tags.iter().filter(|tag| tag.start == start))
+                .collect::<Vec<_>>();
+            opening.sort_by_key(|tag| std::cmp::Reverse(tag.end));
+            for tag in opening {
+                html.push_str(&html_element(tag).unwrap().0);
+                stack.push(tag);
+            }
+
+            html.push_str(&escape(
+                &chars[start as usize..end as usize]
+                    .iter()
+                    .collect::<String>(),
+            ));
+        }
+
+        for tag in stack.iter().rev() {
+            html.push_str(html_element(tag).unwrap().1);
+        }
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_html() {
+        let html = r#"<html><head><title>Page</title><style>b { color: red }</style></head>
+            <body>
+            <!-- StartFragment --><p>Some <strong>bold</strong>,  <em>italic</em>
+            and <mark>marked</mark> text &amp; <code>code()</code>.</p>
+            <p>A <a href="https://example.com/?a=1&amp;b=2">link</a>, a
+            <a href="javascript:alert(1)">script</a> and <span class="x">a span</span>.</p>
+            <ul><li>one</li><li>two<ol><li>first</li><li>second</li></ol></li></ul>
+            <script>alert("no")</script><blink>Still</blink> here<br>&lt;done&gt;&#33;
+            <!-- EndFragment --></body></html>"#;
+
+        let text_with_tags = TextWithTags::from_html(html);
+        assert_eq!(
+            text_with_tags.text,
+            "Some bold, italic and marked text & code().\n\
+             A link, a script and a span.\n\
+             - one\n\
+             - two\n  1. first\n  2. second\n\
+             Still here\n<done>!"
+        );
+        assert_eq!(
+            text_with_tags.clipboard_text(),
+            "Some **bold**, *italic* and ==marked== text & `code()`.\n\
+             A [link](https://example.com/?a=1&b=2), a script and a span.\n\
+             - one\n\
+             - two\n  1. first\n  2. second\n\
+             Still here\n<done>!"
+        );
+    }
+
+    #[test]
+    fn test_from_html_is_lenient() {
+        for html in [
+            "",
+            "<",
+            "<b>unclosed",
+            "</i>closed only",
+            "<a href='x",
+            "a <b>b <i>c</b> d</i>",
+            "&unknown; &#xFFFFFFFF; &",
+            "<pre>  kept\n  </pre>",
+            "<b style=\"font-weight:normal;\" id=\"docs-internal-guid\">not bold</b>",
+        ] {
+            let mut text_with_tags = TextWithTags::from_html(html);
+            assert!(text_with_tags.validate().is_empty(), "{}", html);
+        }
+
+        let text = |html: &str| TextWithTags::from_html(html).clipboard_text();
+        assert_eq!(text("a <b>b <i>c</b> d</i>"), "a **b *c*** *d*");
+        assert_eq!(text("&unknown; &#xFFFFFFFF; &"), "&unknown; &#xFFFFFFFF; &");
+        assert_eq!(text("x<pre>  kept\n  </pre>"), "x\n  kept");
+        assert_eq!(text("<pre>\n  kept</pre>"), "  kept");
+        assert_eq!(
+            text("<b style=\"font-weight: normal\">not bold</b>"),
+            "not bold"
+        );
+    }
+
+    #[test]
+    fn test_from_html_preformatted() {
+        // VS Code puts the lines of the code in a `<div>` styled `white-space: pre`
+        let code = TextWithTags::from_html(include_str!("fixtures/vscode.html"));
+        assert_eq!(
+            code.text,
+            "fn main() {\n    let x = 1;\n\n    //  two spaces\n}"
+        );
+
+        let text = |html: &str| TextWithTags::from_html(html).text;
+        assert_eq!(
+            text("<span style=\"white-space:pre-wrap\">a  b</span> c  d"),
+            "a  b c d"
+        );
+        assert_eq!(
+            text("<div style=\"white-space: pre\"><div>  a</div><div>  b</div></div>  c  d"),
+            "  a\n  b\nc d"
+        );
+    }
+
+    #[test]
+    fn test_html_round_trip() {
+        for markdown in [
+            "**Overl==appin==**==g tag==s",
+            "*a* ~~b~~ <u>c</u>\n`d` [e](https://example.com/?a=1&b=2)",
+            "[[$red]]==urgent== [[$purple]]==later==",
+        ] {
+            let html = TextWithTags::from_markdown(markdown).html_text();
+            assert_eq!(TextWithTags::from_html(&html).clipboard_text(), markdown);
+        }
+    }
+
+    #[test]
+    fn test_html_text() {
+        let html = |markdown: &str| TextWithTags::from_markdown(markdown).html_text();
+
+        assert_eq!(
+            html("plain <text> & \"quotes\""),
+            "plain &lt;text&gt; &amp; &quot;quotes&quot;"
+        );
+        assert_eq!(
+            html("**bold**\n==mark=="),
+            "<b>bold</b><br><mark>mark</mark>"
+        );
+        assert_eq!(
+            html("**Overl==appin==**==g tag==s"),
+            "<b>Overl<mark>appin</mark></b><mark>g tag</mark>s"
+        );
+        assert_eq!(
+            html("*a* ~~b~~ <u>c</u> `d` [e](https://example.com/?a=1&b=2)"),
+            "<i>a</i> <s>b</s> <u>c</u> <code>d</code> \
+             <a href=\"https://example.com/?a=1&amp;b=2\">e</a>"
+        );
+        assert_eq!(
+            html("[[$red]]==urgent=="),
+            "<mark style=\"background-color: #FFC9C9\">urgent</mark>"
+        );
+    }
+}
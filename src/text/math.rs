@@ -0,0 +1,716 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dimension {
+    Bytes,
+    Time,
+}
+
+/// A unit of a math block, `factor` converts it to bytes or seconds.
+#[derive(Debug, PartialEq)]
+struct Unit {
+    names: &'static [&'static str],
+    dimension: Dimension,
+    factor: f64,
+}
+
+const UNITS: &[Unit] = &[
+    Unit {
+        names: &["B", "byte", "bytes"],
+        dimension: Dimension::Bytes,
+        factor: 1.0,
+    },
+    Unit {
+        names: &["KB", "kB", "kb"],
+        dimension: Dimension::Bytes,
+        factor: 1e3,
+    },
+    Unit {
+        names: &["MB", "mb"],
+        dimension: Dimension::Bytes,
+        factor: 1e6,
+    },
+    Unit {
+        names: &["GB", "gb"],
+        dimension: Dimension::Bytes,
+        factor: 1e9,
+    },
+    Unit {
+        names: &["TB", "tb"],
+        dimension: Dimension::Bytes,
+        factor: 1e12,
+    },
+    Unit {
+        names: &["KiB"],
+        dimension: Dimension::Bytes,
+        factor: 1024.0,
+    },
+    Unit {
+        names: &["MiB"],
+        dimension: Dimension::Bytes,
+        factor: 1024.0 * 1024.0,
+    },
+    Unit {
+        names: &["GiB"],
+        dimension: Dimension::Bytes,
+        factor: 1024.0 * 1024.0 * 1024.0,
+    },
+    Unit {
+        names: &["TiB"],
+        dimension: Dimension::Bytes,
+        factor: 1024.0 * 1024.0 * 1024.0 * 1024.0,
+    },
+    Unit {
+        names: &["ns"],
+        dimension: Dimension::Time,
+        factor: 1e-9,
+    },
+    Unit {
+        names: &["us", "µs"],
+        dimension: Dimension::Time,
+        factor: 1e-6,
+    },
+    Unit {
+        names: &["ms"],
+        dimension: Dimension::Time,
+        factor: 1e-3,
+    },
+    Unit {
+        names: &["s", "sec", "secs", "second", "seconds"],
+        dimension: Dimension::Time,
+        factor: 1.0,
+    },
+    Unit {
+        names: &["min", "mins", "minute", "minutes"],
+        dimension: Dimension::Time,
+        factor: 60.0,
+    },
+    Unit {
+        names: &["h", "hr", "hrs", "hour", "hours"],
+        dimension: Dimension::Time,
+        factor: 3600.0,
+    },
+    Unit {
+        names: &["d", "day", "days"],
+        dimension: Dimension::Time,
+        factor: 86400.0,
+    },
+];
+
+fn unit(name: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|unit| unit.names.contains(&name))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Radix {
+    #[default]
+    Decimal,
+    Hex,
+    Binary,
+}
+
+impl Radix {
+    /// Radix of the result of an operation on a value in this one. Only whole numbers have a
+    /// hex or binary form, other results of a value in hex or binary are decimal.
+    fn of(self, amount: f64) -> Self {
+        if is_whole(amount) {
+            self
+        } else {
+            Radix::Decimal
+        }
+    }
+}
+
+/// Whether a number has a hex or binary form.
+fn is_whole(amount: f64) -> bool {
+    amount.fract() == 0.0 && amount.abs() < i64::MAX as f64
+}
+
+/// A result of an expression. Amounts with a unit are kept in bytes or seconds.
+#[derive(Debug, Clone, Copy, Default)]
+struct Value {
+    amount: f64,
+    unit: Option<&'static Unit>,
+    /// `20%`, which adds 20% to what it is added to.
+    percent: bool,
+    /// Set by `in hex` and `in bin`.
+    radix: Radix,
+}
+
+impl Value {
+    fn number(amount: f64) -> Self {
+        Self {
+            amount,
+            ..Default::default()
+        }
+    }
+
+    fn format(&self) -> Option<String> {
+        if !self.amount.is_finite() {
+            return None;
+        }
+        if self.percent {
+            return Some(format!("{}%", format_number(self.amount * 100.0)));
+        }
+        let integer = self.amount as i64;
+        match (self.radix, self.unit) {
+            // only whole numbers have a hex or binary form, `255.5 in hex` has no result
+            (Radix::Hex | Radix::Binary, _) if !is_whole(self.amount) => None,
+            (Radix::Hex, _) => Some(format!("{}0x{:X}", sign(integer), integer.unsigned_abs())),
+            (Radix::Binary, _) => Some(format!("{}0b{:b}", sign(integer), integer.unsigned_abs())),
+            (Radix::Decimal, Some(unit)) => Some(format!(
+                "{} {}",
+                format_number(self.amount / unit.factor),
+                unit.names[0]
+            )),
+            (Radix::Decimal, None) => Some(format_number(self.amount)),
+        }
+    }
+}
+
+fn sign(number: i64) -> &'static str {
+    if number < 0 { "-" } else { "" }
+}
+
+/// Formats a number with up to 10 significant digits, without trailing zeros.
+fn format_number(number: f64) -> String {
+    if number == 0.0 {
+        return "0".to_string();
+    }
+    if number.abs() >= 1e15 || number.abs() < 1e-9 {
+        return format!("{:e}", number);
+    }
+    let decimals = (9 - number.abs().log10().floor() as i32).clamp(0, 10) as usize;
+    let text = format!("{:.*}", decimals, number);
+    let text = if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &text
+    };
+    match text {
+        "-0" => "0".to_string(),
+        text => text.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(char),
+}
+
+fn tokenize(line: &str) -> Option<Vec<Token>> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '0' && matches!(chars.get(i + 1), Some('x' | 'X' | 'b' | 'B')) {
+            let base = match chars[i + 1] {
+                'x' | 'X' => 16,
+                _ => 2,
+            };
+            let digits = chars[i + 2..]
+                .iter()
+                .take_while(|c| c.is_digit(base) || **c == '_')
+                .filter(|c| **c != '_')
+                .collect::<String>();
+            let length = chars[i + 2..]
+                .iter()
+                .take_while(|c| c.is_digit(base) || **c == '_')
+                .count();
+            // `0b` alone is zero bytes, not a binary number
+            if length == 0 {
+                tokens.push(Token::Number(0.0));
+                i += 1;
+                continue;
+            }
+            let number = i64::from_str_radix(&digits, base).ok()?;
+            tokens.push(Token::Number(number as f64));
+            i += 2 + length;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let mut end = i;
+            while end < chars.len() && (chars[end].is_ascii_digit() || chars[end] == '.') {
+                end += 1;
+            }
+            // `1e3` and `1e-3`, but not `1em` or `2 ms`
+            if chars.get(end) == Some(&'e') {
+                let digits = match chars.get(end + 1) {
+                    Some('-' | '+') => end + 2,
+                    _ => end + 1,
+                };
+                if chars.get(digits).is_some_and(char::is_ascii_digit) {
+                    end = digits;
+                    while end < chars.len() && chars[end].is_ascii_digit() {
+                        end += 1;
+                    }
+                }
+            }
+            let number = chars[i..end].iter().collect::<String>().parse().ok()?;
+            tokens.push(Token::Number(number));
+            i = end;
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = i;
+            while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            tokens.push(Token::Ident(chars[i..end].iter().collect()));
+            i = end;
+        } else {
+            let symbol = match c {
+                '×' => '*',
+                '÷' => '/',
+                '+' | '-' | '*' | '/' | '^' | '%' | '(' | ')' | '=' => c,
+                _ => return None,
+            };
+            tokens.push(Token::Symbol(symbol));
+            i += 1;
+        }
+    }
+
+    Some(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    variables: &'a HashMap<String, Value>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> bool {
+        let found = self.peek() == Some(&Token::Symbol(symbol));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_word(&mut self, words: &[&str]) -> bool {
+        let found =
+            matches!(self.peek(), Some(Token::Ident(word)) if words.contains(&word.as_str()));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    /// `expression (in | to | as) unit`
+    fn conversion(&mut self) -> Option<Value> {
+        let value = self.additive()?;
+        if !self.eat_word(&["in", "to", "as"]) {
+            return Some(value);
+        }
+
+        let Some(Token::Ident(target)) = self.peek().cloned() else {
+            return None;
+        };
+        self.position += 1;
+        let radix = match target.as_str() {
+            "hex" => Radix::Hex,
+            "bin" | "binary" => Radix::Binary,
+            "dec" | "decimal" => Radix::Decimal,
+            _ => {
+                let unit = unit(&target)?;
+                return match value.unit {
+                    Some(from) if from.dimension == unit.dimension => Some(Value {
+                        unit: Some(unit),
+                        radix: Radix::Decimal,
+                        ..value
+                    }),
+                    _ => None,
+                };
+            }
+        };
+        (value.unit.is_none() && !value.percent).then_some(Value { radix, ..value })
+    }
+
+    fn additive(&mut self) -> Option<Value> {
+        let mut value = self.term()?;
+        loop {
+            let sign = if self.eat_symbol('+') {
+                1.0
+            } else if self.eat_symbol('-') {
+                -1.0
+            } else {
+                return Some(value);
+            };
+            let right = self.term()?;
+
+            value = if right.percent && !value.percent {
+                Value {
+                    amount: value.amount * (1.0 + sign * right.amount),
+                    ..value
+                }
+            } else if right.percent != value.percent {
+                return None;
+            } else {
+                let unit = match (value.unit, right.unit) {
+                    (Some(left), Some(right)) if left.dimension != right.dimension => return None,
+                    (left, right) => left.or(right),
+                };
+                let amount = value.amount + sign * right.amount;
+                Value {
+                    amount,
+                    unit,
+                    radix: value.radix.of(amount),
+                    ..value
+                }
+            };
+        }
+    }
+
+    fn term(&mut self) -> Option<Value> {
+        let mut value = self.power()?;
+        loop {
+            let operator = if self.eat_symbol('*') {
+                '*'
+            } else if self.eat_symbol('/') {
+                '/'
+            } else if self.eat_word(&["mod"]) {
+                'm'
+            } else {
+                return Some(value);
+            };
+            let right = self.power()?;
+
+            let unit = match (operator, value.unit, right.unit) {
+                ('*', Some(_), Some(_)) => return None,
+                ('*', left, right) => left.or(right),
+                (_, Some(left), Some(right)) if left.dimension != right.dimension => return None,
+                ('/', Some(_), Some(_)) => None,
+                (_, None, Some(_)) => return None,
+                (_, left, _) => left,
+            };
+            let amount = match operator {
+                '*' => value.amount * right.amount,
+                '/' => value.amount / right.amount,
+                _ => value.amount.rem_euclid(right.amount),
+            };
+            value = Value {
+                amount,
+                unit,
+                percent: false,
+                radix: value.radix.of(amount),
+            };
+        }
+    }
+
+    fn power(&mut self) -> Option<Value> {
+        let value = self.unary()?;
+        if !self.eat_symbol('^') {
+            return Some(value);
+        }
+        let exponent = self.power()?;
+        if value.unit.is_some() || exponent.unit.is_some() {
+            return None;
+        }
+        let amount = value.amount.powf(exponent.amount);
+        Some(Value {
+            amount,
+            radix: value.radix.of(amount),
+            ..value
+        })
+    }
+
+    fn unary(&mut self) -> Option<Value> {
+        if self.eat_symbol('-') {
+            let value = self.unary()?;
+            return Some(Value {
+                amount: -value.amount,
+                ..value
+            });
+        }
+        if self.eat_symbol('+') {
+            return self.unary();
+        }
+        self.postfix()
+    }
+
+    /// A value followed by a unit or a percent sign, e.g. `5 MB`, `20%` or `20% of 5`.
+    fn postfix(&mut self) -> Option<Value> {
+        let mut value = self.primary()?;
+
+        if let Some(Token::Ident(name)) = self.peek()
+            && let Some(unit) = unit(name)
+        {
+            if value.unit.is_some() || value.percent {
+                return None;
+            }
+            self.position += 1;
+            value = Value {
+                amount: value.amount * unit.factor,
+                unit: Some(unit),
+                radix: Radix::Decimal,
+                ..value
+            };
+        }
+
+        if self.eat_symbol('%') {
+            if value.unit.is_some() {
+                return None;
+            }
+            value = Value {
+                amount: value.amount / 100.0,
+                percent: true,
+                radix: Radix::Decimal,
+                ..value
+            };
+            if self.eat_word(&["of"]) {
+                let of = self.power()?;
+                value = Value {
+                    amount: value.amount * of.amount,
+                    percent: of.percent,
+                    ..of
+                };
+            }
+        }
+
+        Some(value)
+    }
+
+    fn primary(&mut self) -> Option<Value> {
+        let token = self.peek()?.clone();
+        self.position += 1;
+        match token {
+            Token::Number(amount) => Some(Value::number(amount)),
+            Token::Symbol('(') => {
+                let value = self.conversion()?;
+                self.eat_symbol(')').then_some(value)
+            }
+            Token::Ident(name) => {
+                if let Some(value) = self.variables.get(&name) {
+                    return Some(*value);
+                }
+                match name.as_str() {
+                    "pi" => return Some(Value::number(std::f64::consts::PI)),
+                    "e" => return Some(Value::number(std::f64::consts::E)),
+                    _ => {}
+                }
+
+                if !self.eat_symbol('(') {
+                    return None;
+                }
+                let argument = self.conversion()?;
+                if !self.eat_symbol(')') || argument.percent {
+                    return None;
+                }
+                let apply = |function: fn(f64) -> f64| Value {
+                    amount: function(argument.amount),
+                    ..argument
+                };
+                match name.as_str() {
+                    "abs" => Some(apply(f64::abs)),
+                    "round" => Some(apply(f64::round)),
+                    "floor" => Some(apply(f64::floor)),
+                    "ceil" => Some(apply(f64::ceil)),
+                    _ if argument.unit.is_some() => None,
+                    "sqrt" => Some(apply(f64::sqrt)),
+                    "ln" => Some(apply(f64::ln)),
+                    "log" => Some(apply(f64::log10)),
+                    "sin" => Some(apply(f64::sin)),
+                    "cos" => Some(apply(f64::cos)),
+                    "tan" => Some(apply(f64::tan)),
+                    _ => None,
+                }
+            }
+            Token::Symbol(_) => None,
+        }
+    }
+}
+
+fn evaluate_line(line: &str, variables: &mut HashMap<String, Value>) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+        return None;
+    }
+
+    let mut tokens = tokenize(line)?;
+    let name = match tokens.as_slice() {
+        [Token::Ident(name), Token::Symbol('='), ..] => {
+            let name = name.clone();
+            tokens.drain(..2);
+            Some(name)
+        }
+        _ => None,
+    };
+
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        variables,
+    };
+    let value = parser.conversion()?;
+    if parser.position != parser.tokens.len() {
+        return None;
+    }
+
+    let result = value.format()?;
+    if let Some(name) = name {
+        variables.insert(name, value);
+    }
+    Some(result)
+}
+
+/// Evaluates every line of a math block as an expression, `None` for lines that are empty,
+/// comments or not an expression. A line `name = expression` assigns a variable for the lines
+/// below it.
+pub fn evaluate(text: &str) -> Vec<Option<String>> {
+    let mut variables = HashMap::new();
+    text.split('\n')
+        .map(|line| evaluate_line(line, &mut variables))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(text: &str) -> Vec<Option<String>> {
+        evaluate(text)
+    }
+
+    fn expected(results: &[Option<&str>]) -> Vec<Option<String>> {
+        results
+            .iter()
+            .map(|result| result.map(str::to_string))
+            .collect()
+    }
+
+    fn result(line: &str) -> Option<String> {
+        evaluate(line).remove(0)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        for (line, expected) in [
+            ("1 + 2 * 3", "7"),
+            ("(1 + 2) * 3", "9"),
+            ("2 ^ 3 ^ 2", "512"),
+            ("-3 + 1", "-2"),
+            ("10 / 4", "2.5"),
+            ("1 / 3", "0.3333333333"),
+            ("1 / 3 * 3", "1"),
+            ("10 mod 3", "1"),
+            ("0.1 + 0.2", "0.3"),
+            ("1.5e3 × 2", "3000"),
+            ("sqrt(16) + abs(-2)", "6"),
+            ("round(pi * 100) / 100", "3.14"),
+            ("2 ^ 64", "1.8446744073709552e19"),
+        ] {
+            assert_eq!(result(line).as_deref(), Some(expected), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(
+            results("price = 40\nqty = 3\n\n# total\nprice * qty\nunknown * 2\nqty"),
+            expected(&[
+                Some("40"),
+                Some("3"),
+                None,
+                None,
+                Some("120"),
+                None,
+                Some("3")
+            ])
+        );
+        // a line that fails does not assign
+        assert_eq!(results("a = 1 +\na"), expected(&[None, None]));
+        // a hex variable divided into a fraction gives it in decimal
+        assert_eq!(
+            results("x = 255 in hex\nx / 2\nx + 1\nx + 0.5\nx ^ 0.5 * 2\nx / 2 in hex"),
+            expected(&[
+                Some("0xFF"),
+                Some("127.5"),
+                Some("0x100"),
+                Some("255.5"),
+                Some("31.93743885"),
+                None
+            ])
+        );
+    }
+
+    #[test]
+    fn test_percentages() {
+        for (line, expected) in [
+            ("20%", "20%"),
+            ("200 + 10%", "220"),
+            ("200 - 25%", "150"),
+            ("200 * 10%", "20"),
+            ("50% of 300", "150"),
+            ("10% + 5%", "15%"),
+            ("5% of 2 GB", "0.1 GB"),
+        ] {
+            assert_eq!(result(line).as_deref(), Some(expected), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_radix() {
+        for (line, expected) in [
+            ("0xFF + 1", "256"),
+            ("0b1010", "10"),
+            ("255 in hex", "0xFF"),
+            ("10 to bin", "0b1010"),
+            ("-0x10 in hex", "-0x10"),
+            ("0x10 in dec", "16"),
+            ("255.5 in dec", "255.5"),
+            ("0.5 * 4 in bin", "0b10"),
+        ] {
+            assert_eq!(result(line).as_deref(), Some(expected), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_units() {
+        for (line, expected) in [
+            ("5 MB + 512 KB", "5.512 MB"),
+            ("1.5 GB in MB", "1500 MB"),
+            ("2 MiB in KiB", "2048 KiB"),
+            ("1500 ms in s", "1.5 s"),
+            ("10 ms * 3", "30 ms"),
+            ("3 MB / 1 KB", "3000"),
+            ("1 GB / 2", "0.5 GB"),
+            ("2 h + 30 min in min", "150 min"),
+            ("(1 + 1) KB", "2 KB"),
+        ] {
+            assert_eq!(result(line).as_deref(), Some(expected), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        for line in [
+            "",
+            "   ",
+            "# a comment",
+            "// a comment",
+            "hello world",
+            "1 +",
+            "(1 + 2",
+            "5 MB + 2 s",
+            "5 MB * 2 KB",
+            "2 / 1 s",
+            "5 MB in hex",
+            "255.5 in hex",
+            "1 / 2 to bin",
+            "2 ^ 64 in hex",
+            "1 / 0",
+            "1 $ 2",
+        ] {
+            assert_eq!(result(line), None, "{}", line);
+        }
+    }
+}
@@ -1,3 +1,20 @@
+mod block;
+mod detect;
+mod document;
+mod hashtag;
+mod html;
+mod math;
+mod page_ref;
+mod syntax;
+mod task;
 mod text_buffer;
 
+pub use block::*;
+pub use detect::*;
+pub use document::*;
+pub use hashtag::*;
+pub use math::*;
+pub use page_ref::*;
+pub use syntax::*;
+pub use task::*;
 pub use text_buffer::*;
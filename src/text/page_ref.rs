@@ -0,0 +1,66 @@
+/// Buffer tag drawing `[[page]]` references, which stay plain text in the block.
+pub const PAGE_REF_TAG: &str = "page-ref";
+
+/// Char ranges of the `[[page]]` references in a text, brackets included. Logseq's
+/// `[[$red]]` highlight colours are not references.
+pub fn page_refs(text: &str) -> Vec<(i32, i32)> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut refs = vec![];
+    let mut i = 0;
+
+    while i + 1 < chars.len() {
+        if chars[i] != '[' || chars[i + 1] != '[' {
+            i += 1;
+            continue;
+        }
+
+        let name_start = i + 2;
+        let end = (name_start..chars.len())
+            .take_while(|&j| !matches!(chars[j], '[' | '\n'))
+            .find(|&j| chars[j] == ']' && chars.get(j + 1) == Some(&']'));
+        match end {
+            Some(end) if end > name_start && chars[name_start] != '$' => {
+                refs.push((i as i32, end as i32 + 2));
+                i = end + 2;
+            }
+            _ => i += 1,
+        }
+    }
+
+    refs
+}
+
+/// What was typed after an unclosed `[[` right before the cursor, with the char offset of
+/// the `[[` in `line`, the text of the line up to the cursor.
+pub fn page_ref_query(line: &str) -> Option<(i32, &str)> {
+    let start = line.rfind("[[")?;
+    let query = &line[start + 2..];
+    if query.contains(']') || query.starts_with('$') {
+        return None;
+    }
+    Some((line[..start].chars().count() as i32, query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_refs() {
+        assert_eq!(
+            page_refs("See [[Rust]] and [[project/illpad]]."),
+            vec![(4, 12), (17, 35)]
+        );
+        assert_eq!(page_refs("글 [[한글]]"), vec![(2, 8)]);
+        assert_eq!(page_refs("[[$red]] [[]] [[a\nb]] [[ [[b]]"), vec![(25, 30)]);
+    }
+
+    #[test]
+    fn test_page_ref_query() {
+        assert_eq!(page_ref_query("see [[Ru"), Some((4, "Ru")));
+        assert_eq!(page_ref_query("글 [["), Some((2, "")));
+        assert_eq!(page_ref_query("see [[Rust]] and"), None);
+        assert_eq!(page_ref_query("[[$re"), None);
+        assert_eq!(page_ref_query("no ref"), None);
+    }
+}
@@ -0,0 +1,516 @@
+use super::BlockMode;
+
+/// Kind of a highlighted token, drawn with the buffer tag of `tag_name`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword,
+    String,
+    Number,
+    Comment,
+    Type,
+    Function,
+    Property,
+    Variable,
+    Heading,
+    Punctuation,
+}
+
+impl TokenKind {
+    pub const ALL: [TokenKind; 10] = [
+        TokenKind::Keyword,
+        TokenKind::String,
+        TokenKind::Number,
+        TokenKind::Comment,
+        TokenKind::Type,
+        TokenKind::Function,
+        TokenKind::Property,
+        TokenKind::Variable,
+        TokenKind::Heading,
+        TokenKind::Punctuation,
+    ];
+
+    pub fn tag_name(&self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "syntax-keyword",
+            TokenKind::String => "syntax-string",
+            TokenKind::Number => "syntax-number",
+            TokenKind::Comment => "syntax-comment",
+            TokenKind::Type => "syntax-type",
+            TokenKind::Function => "syntax-function",
+            TokenKind::Property => "syntax-property",
+            TokenKind::Variable => "syntax-variable",
+            TokenKind::Heading => "syntax-heading",
+            TokenKind::Punctuation => "syntax-punctuation",
+        }
+    }
+
+    pub fn foreground(&self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "#CF222E",
+            TokenKind::String => "#0A3069",
+            TokenKind::Number | TokenKind::Property | TokenKind::Heading => "#0550AE",
+            TokenKind::Comment | TokenKind::Punctuation => "#6E7781",
+            TokenKind::Type | TokenKind::Variable => "#953800",
+            TokenKind::Function => "#8250DF",
+        }
+    }
+}
+
+/// A highlighted range of a text, in chars.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub start: i32,
+    pub end: i32,
+    pub kind: TokenKind,
+}
+
+struct Language {
+    keywords: &'static [&'static str],
+    case_insensitive: bool,
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+}
+
+const RUST: Language = Language {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ],
+    case_insensitive: false,
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+};
+
+const JSON: Language = Language {
+    keywords: &["true", "false", "null"],
+    case_insensitive: false,
+    line_comments: &[],
+    block_comment: None,
+    quotes: &['"'],
+};
+
+const SHELL: Language = Language {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+        "in", "local", "return", "then", "until", "while",
+    ],
+    case_insensitive: false,
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const SQL: Language = Language {
+    keywords: &[
+        "add", "all", "alter", "and", "as", "asc", "between", "by", "case", "create", "delete",
+        "desc", "distinct", "drop", "else", "end", "exists", "from", "group", "having", "in",
+        "index", "inner", "insert", "into", "is", "join", "left", "like", "limit", "not", "null",
+        "offset", "on", "or", "order", "outer", "primary", "key", "right", "select", "set",
+        "table", "then", "union", "update", "values", "when", "where", "with",
+    ],
+    case_insensitive: true,
+    line_comments: &["--"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['\'', '"'],
+};
+
+const MATH: Language = Language {
+    keywords: &["in", "to", "as", "of", "mod", "hex", "bin", "dec"],
+    case_insensitive: false,
+    line_comments: &["#", "//"],
+    block_comment: None,
+    quotes: &[],
+};
+
+/// Splits the text of a block into highlighted tokens, text blocks have none.
+pub fn highlight(mode: BlockMode, text: &str) -> Vec<Token> {
+    let chars = text.chars().collect::<Vec<_>>();
+    match mode {
+        BlockMode::Text => vec![],
+        BlockMode::Markdown => highlight_markdown(&chars),
+        BlockMode::Rust => highlight_code(&chars, &RUST, mode),
+        BlockMode::Json => highlight_code(&chars, &JSON, mode),
+        BlockMode::Shell => highlight_code(&chars, &SHELL, mode),
+        BlockMode::Sql => highlight_code(&chars, &SQL, mode),
+        BlockMode::Math => highlight_code(&chars, &MATH, mode),
+    }
+}
+
+fn starts_with(chars: &[char], offset: usize, pattern: &str) -> bool {
+    pattern
+        .chars()
+        .enumerate()
+        .all(|(i, c)| chars.get(offset + i) == Some(&c))
+}
+
+fn find_from(chars: &[char], offset: usize, pattern: &str) -> Option<usize> {
+    (offset..chars.len()).find(|&i| starts_with(chars, i, pattern))
+}
+
+fn line_end(chars: &[char], offset: usize) -> usize {
+    (offset..chars.len())
+        .find(|&i| chars[i] == '\n')
+        .unwrap_or(chars.len())
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn highlight_code(chars: &[char], language: &Language, mode: BlockMode) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut push = |start: usize, end: usize, kind: TokenKind| {
+        tokens.push(Token {
+            start: start as i32,
+            end: end as i32,
+            kind,
+        })
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let at_word_start = i == 0 || !is_ident(chars[i - 1]);
+
+        // `#` only starts a shell or math comment at the start of a word, not in `$#` or `a#b`
+        let line_comment = language.line_comments.iter().any(|comment| {
+            starts_with(chars, i, comment)
+                && (*comment != "#" || i == 0 || chars[i - 1].is_whitespace())
+        });
+        if line_comment {
+            let end = line_end(chars, i);
+            push(i, end, TokenKind::Comment);
+            i = end;
+            continue;
+        }
+
+        if let Some((open, close)) = language.block_comment
+            && starts_with(chars, i, open)
+        {
+            let end = find_from(chars, i + open.len(), close)
+                .map_or(chars.len(), |end| end + close.len());
+            push(i, end, TokenKind::Comment);
+            i = end;
+            continue;
+        }
+
+        if language.quotes.contains(&c) {
+            // `'a` is a lifetime in Rust, only `'a'` and `'\n'` are chars
+            let is_lifetime = mode == BlockMode::Rust
+                && c == '\''
+                && chars.get(i + 1) != Some(&'\\')
+                && chars.get(i + 2) != Some(&'\'');
+            if !is_lifetime {
+                let mut end = i + 1;
+                while end < chars.len() && chars[end] != c {
+                    // shell single quotes have no escapes
+                    if chars[end] == '\\' && !(mode == BlockMode::Shell && c == '\'') {
+                        end += 1;
+                    }
+                    end += 1;
+                }
+                let end = (end + 1).min(chars.len());
+
+                let mut next = end;
+                while next < chars.len() && chars[next].is_whitespace() {
+                    next += 1;
+                }
+                let is_key = mode == BlockMode::Json && chars.get(next) == Some(&':');
+                push(
+                    i,
+                    end,
+                    if is_key {
+                        TokenKind::Property
+                    } else {
+                        TokenKind::String
+                    },
+                );
+                i = end;
+                continue;
+            }
+        }
+
+        if mode == BlockMode::Shell && c == '$' {
+            let end = match chars.get(i + 1) {
+                Some('{') => find_from(chars, i, "}").map_or(chars.len(), |end| end + 1),
+                _ => {
+                    let mut end = i + 1;
+                    while end < chars.len() && is_ident(chars[end]) {
+                        end += 1;
+                    }
+                    end
+                }
+            };
+            if end > i + 1 {
+                push(i, end, TokenKind::Variable);
+                i = end;
+                continue;
+            }
+        }
+
+        if c.is_ascii_digit() && at_word_start {
+            let mut end = i + 1;
+            while end < chars.len()
+                && (is_ident(chars[end])
+                    || (chars[end] == '.' && chars.get(end + 1).is_some_and(char::is_ascii_digit)))
+            {
+                end += 1;
+            }
+            push(i, end, TokenKind::Number);
+            i = end;
+            continue;
+        }
+
+        if is_ident(c) && at_word_start {
+            let mut end = i + 1;
+            while end < chars.len() && is_ident(chars[end]) {
+                end += 1;
+            }
+            let word = chars[i..end].iter().collect::<String>();
+
+            let is_keyword = if language.case_insensitive {
+                language.keywords.contains(&word.to_lowercase().as_str())
+            } else {
+                language.keywords.contains(&word.as_str())
+            };
+            let kind = if is_keyword {
+                Some(TokenKind::Keyword)
+            } else if mode == BlockMode::Rust && matches!(chars.get(end), Some('!' | '(')) {
+                Some(TokenKind::Function)
+            } else if mode == BlockMode::Rust && c.is_uppercase() {
+                Some(TokenKind::Type)
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                push(i, end, kind);
+            }
+            i = end;
+            continue;
+        }
+
+        i += 1;
+    }
+
+    tokens
+}
+
+fn highlight_markdown(chars: &[char]) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut push = |start: usize, end: usize, kind: TokenKind| {
+        tokens.push(Token {
+            start: start as i32,
+            end: end as i32,
+            kind,
+        })
+    };
+
+    let mut line_start = 0;
+    let mut in_fence = false;
+    while line_start < chars.len() {
+        let end = line_end(chars, line_start);
+        let line = &chars[line_start..end];
+        let indent = line
+            .iter()
+            .take_while(|c| **c == ' ' || **c == '\t')
+            .count();
+        let content = &line[indent..];
+
+        if starts_with(content, 0, "```") {
+            in_fence = !in_fence;
+            push(line_start, end, TokenKind::Punctuation);
+        } else if in_fence {
+            push(line_start, end, TokenKind::String);
+        } else if content.first() == Some(&'#') {
+            push(line_start, end, TokenKind::Heading);
+        } else if content.first() == Some(&'>') {
+            push(line_start, end, TokenKind::Comment);
+        } else {
+            let digits = content.iter().take_while(|c| c.is_ascii_digit()).count();
+            let marker = match content {
+                ['-' | '*' | '+', ' ', ..] => 1,
+                _ if digits > 0 && starts_with(content, digits, ". ") => digits + 1,
+                _ => 0,
+            };
+            if marker > 0 {
+                push(
+                    line_start + indent,
+                    line_start + indent + marker,
+                    TokenKind::Keyword,
+                );
+            }
+
+            let mut i = indent + marker;
+            while i < line.len() {
+                if line[i] == '`' {
+                    if let Some(close) = (i + 1..line.len()).find(|&j| line[j] == '`') {
+                        push(line_start + i, line_start + close + 1, TokenKind::String);
+                        i = close + 1;
+                        continue;
+                    }
+                } else if ["**", "__", "==", "~~"]
+                    .iter()
+                    .any(|m| starts_with(line, i, m))
+                {
+                    push(line_start + i, line_start + i + 2, TokenKind::Punctuation);
+                    i += 2;
+                    continue;
+                }
+                i += 1;
+            }
+        }
+
+        line_start = end + 1;
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(mode: BlockMode, text: &str) -> Vec<(String, TokenKind)> {
+        let chars = text.chars().collect::<Vec<_>>();
+        highlight(mode, text)
+            .into_iter()
+            .map(|t| {
+                let token = chars[t.start as usize..t.end as usize].iter().collect();
+                (token, t.kind)
+            })
+            .collect()
+    }
+
+    fn kinds(mode: BlockMode, text: &str) -> Vec<(&'static str, String)> {
+        tokens(mode, text)
+            .into_iter()
+            .map(|(token, kind)| (kind.tag_name().trim_start_matches("syntax-"), token))
+            .collect()
+    }
+
+    fn expected(tokens: &[(&'static str, &str)]) -> Vec<(&'static str, String)> {
+        tokens
+            .iter()
+            .map(|(kind, token)| (*kind, token.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rust() {
+        assert_eq!(
+            kinds(
+                BlockMode::Rust,
+                "fn f(s: &'a str) -> Vec<u8> { // bytes\n    println!(\"{}\\\"\", 0x1F, '\\n') }"
+            ),
+            expected(&[
+                ("keyword", "fn"),
+                ("function", "f"),
+                ("type", "Vec"),
+                ("comment", "// bytes"),
+                ("function", "println"),
+                ("string", "\"{}\\\"\""),
+                ("number", "0x1F"),
+                ("string", "'\\n'"),
+            ])
+        );
+        assert_eq!(
+            kinds(BlockMode::Rust, "/* a\nb */ let x2 = 1.5;"),
+            expected(&[
+                ("comment", "/* a\nb */"),
+                ("keyword", "let"),
+                ("number", "1.5")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            kinds(BlockMode::Json, r#"{"a" : [1, -2.5e3, "b"], "c": null}"#),
+            expected(&[
+                ("property", "\"a\""),
+                ("number", "1"),
+                ("number", "2.5e3"),
+                ("string", "\"b\""),
+                ("property", "\"c\""),
+                ("keyword", "null"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_shell() {
+        assert_eq!(
+            kinds(
+                BlockMode::Shell,
+                "if [ $# -gt 0 ]; then echo \"${HOME}\" 'a\\' # done\nfi"
+            ),
+            expected(&[
+                ("keyword", "if"),
+                ("number", "0"),
+                ("keyword", "then"),
+                ("string", "\"${HOME}\""),
+                ("string", "'a\\'"),
+                ("comment", "# done"),
+                ("keyword", "fi"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_sql() {
+        assert_eq!(
+            kinds(
+                BlockMode::Sql,
+                "SELECT name FROM users -- all\nwhere id = 'x';"
+            ),
+            expected(&[
+                ("keyword", "SELECT"),
+                ("keyword", "FROM"),
+                ("comment", "-- all"),
+                ("keyword", "where"),
+                ("string", "'x'"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_math() {
+        assert_eq!(
+            kinds(BlockMode::Math, "# sizes\nx = 0xFF + 2 MB in KB"),
+            expected(&[
+                ("comment", "# sizes"),
+                ("number", "0xFF"),
+                ("number", "2"),
+                ("keyword", "in"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(
+            kinds(
+                BlockMode::Markdown,
+                "# Title\n- **a** `b`\n12. c\n> quote\n```\n# not a title\n```"
+            ),
+            expected(&[
+                ("heading", "# Title"),
+                ("keyword", "-"),
+                ("punctuation", "**"),
+                ("punctuation", "**"),
+                ("string", "`b`"),
+                ("keyword", "12."),
+                ("comment", "> quote"),
+                ("punctuation", "```"),
+                ("string", "# not a title"),
+                ("punctuation", "```"),
+            ])
+        );
+        assert!(highlight(BlockMode::Text, "# Title").is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+/// Logseq task state of a block, written as a marker at the start of its text like
+/// `TODO buy milk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Todo,
+    Doing,
+    Done,
+    Later,
+    Now,
+}
+
+impl TaskState {
+    const ALL: [TaskState; 5] = [
+        TaskState::Todo,
+        TaskState::Doing,
+        TaskState::Done,
+        TaskState::Later,
+        TaskState::Now,
+    ];
+
+    pub fn marker(&self) -> &'static str {
+        match self {
+            TaskState::Todo => "TODO",
+            TaskState::Doing => "DOING",
+            TaskState::Done => "DONE",
+            TaskState::Later => "LATER",
+            TaskState::Now => "NOW",
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        *self == TaskState::Done
+    }
+
+    /// The state after `state` when cycling with the keyboard, `TODO → DOING → DONE → none`
+    /// and `LATER → NOW → DONE → none` like in Logseq.
+    pub fn cycle(state: Option<TaskState>) -> Option<TaskState> {
+        match state {
+            None => Some(TaskState::Todo),
+            Some(TaskState::Todo) => Some(TaskState::Doing),
+            Some(TaskState::Later) => Some(TaskState::Now),
+            Some(TaskState::Doing | TaskState::Now) => Some(TaskState::Done),
+            Some(TaskState::Done) => None,
+        }
+    }
+}
+
+/// The task state of a block and the length in chars of its marker with the space after it.
+pub fn task_marker(text: &str) -> Option<(TaskState, i32)> {
+    TaskState::ALL.into_iter().find_map(|state| {
+        let rest = text.strip_prefix(state.marker())?;
+        match rest.chars().next() {
+            None => Some((state, state.marker().len() as i32)),
+            Some(' ') => Some((state, state.marker().len() as i32 + 1)),
+            Some(_) => None,
+        }
+    })
+}
+
+/// The edit that gives a block the task `state`: the number of chars to replace at the start
+/// of its text and what to replace them with.
+pub fn task_edit(text: &str, state: Option<TaskState>) -> (i32, String) {
+    let len = task_marker(text).map_or(0, |(_, len)| len);
+    let marker = match state {
+        Some(state) => format!("{} ", state.marker()),
+        None => String::new(),
+    };
+    (len, marker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_marker() {
+        assert_eq!(task_marker("TODO buy milk"), Some((TaskState::Todo, 5)));
+        assert_eq!(task_marker("DONE"), Some((TaskState::Done, 4)));
+        assert_eq!(task_marker("NOW call"), Some((TaskState::Now, 4)));
+        assert_eq!(task_marker("TODOS"), None);
+        assert_eq!(task_marker(" TODO indented"), None);
+        assert_eq!(task_marker("todo lower case"), None);
+    }
+
+    #[test]
+    fn test_task_edit() {
+        let mut text = String::from("buy milk");
+        let mut state = None;
+        let mut seen = vec![];
+
+        for _ in 0..4 {
+            state = TaskState::cycle(state);
+            let (len, marker) = task_edit(&text, state);
+            text.replace_range(..len as usize, &marker);
+            seen.push(text.clone());
+        }
+
+        assert_eq!(
+            seen,
+            vec![
+                "TODO buy milk",
+                "DOING buy milk",
+                "DONE buy milk",
+                "buy milk"
+            ]
+        );
+        assert_eq!(
+            task_edit("LATER", Some(TaskState::Now)),
+            (5, String::from("NOW "))
+        );
+    }
+}
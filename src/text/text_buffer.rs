@@ -1,11 +1,227 @@
-use gtk::{TextBuffer, TextIter, TextTag, prelude::*};
+use gtk::{TextBuffer, TextIter, TextTag, pango, prelude::*};
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, ops::Range};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use super::{Document, HASHTAG_TAG, PAGE_REF_TAG, TokenKind};
+
+/// Markdown syntax of a tag, used to carry it through the clipboard (e.g. to and from Logseq).
+#[derive(PartialEq)]
+struct MarkdownMarker {
+    name: &'static str,
+    open: &'static str,
+    close: &'static str,
+    /// Whether the marker may be used inside a word, `_` may not (e.g. `snake_case`).
+    intraword: bool,
+}
+
+/// The first marker of a tag is the one `clipboard_text` writes, the others are only parsed.
+/// Longer markers come first so that `**` is not read as two `*`.
+const MARKDOWN_MARKERS: &[MarkdownMarker] = &[
+    MarkdownMarker {
+        name: "bold",
+        open: "**",
+        close: "**",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "strikethrough",
+        open: "~~",
+        close: "~~",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "highlight",
+        open: "==",
+        close: "==",
+        intraword: true,
+    },
+    // Logseq's coloured highlights, written as `[[$red]]==text==`
+    MarkdownMarker {
+        name: "highlight-red",
+        open: "[[$red]]==",
+        close: "==",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "highlight-green",
+        open: "[[$green]]==",
+        close: "==",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "highlight-blue",
+        open: "[[$blue]]==",
+        close: "==",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "highlight-purple",
+        open: "[[$purple]]==",
+        close: "==",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "highlight",
+        open: "[[$yellow]]==",
+        close: "==",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "underline",
+        open: "<u>",
+        close: "</u>",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "italic",
+        open: "*",
+        close: "*",
+        intraword: true,
+    },
+    MarkdownMarker {
+        name: "italic",
+        open: "_",
+        close: "_",
+        intraword: false,
+    },
+    MarkdownMarker {
+        name: "code",
+        open: "`",
+        close: "`",
+        intraword: true,
+    },
+    // written as `[text](href)`
+    MarkdownMarker {
+        name: "link",
+        open: "[",
+        close: "]",
+        intraword: true,
+    },
+];
+
+/// Buffer tag drawing the text of done tasks, it is not part of the text.
+pub const TASK_DONE_TAG: &str = "task-done";
+
+/// Names of the tags a `TextWithTags` may carry.
+pub const TAG_NAMES: &[&str] = &[
+    "highlight",
+    "highlight-red",
+    "highlight-green",
+    "highlight-blue",
+    "highlight-purple",
+    "bold",
+    "italic",
+    "strikethrough",
+    "underline",
+    "code",
+    "link",
+];
+
+pub struct HighlightColour {
+    pub name: &'static str,
+    pub background: &'static str,
+}
+
+/// Highlight colours in the order of their Ctrl + 1..5 shortcuts. Yellow keeps the `highlight`
+/// name it had before there were colours, so older notes still load as yellow.
+pub const HIGHLIGHT_COLOURS: &[HighlightColour] = &[
+    HighlightColour {
+        name: "highlight",
+        background: "#FEF3AC",
+    },
+    HighlightColour {
+        name: "highlight-red",
+        background: "#FFC9C9",
+    },
+    HighlightColour {
+        name: "highlight-green",
+        background: "#C6EFCE",
+    },
+    HighlightColour {
+        name: "highlight-blue",
+        background: "#C9E2FF",
+    },
+    HighlightColour {
+        name: "highlight-purple",
+        background: "#E6D4FA",
+    },
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Tag {
     pub start: i32,
     pub end: i32,
     pub name: String,
+    /// Data carried by the tag, e.g. the `href` of a `link`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attrs: BTreeMap<String, String>,
+}
+
+impl Tag {
+    pub fn link(start: i32, end: i32, href: &str) -> Self {
+        Self {
+            start,
+            end,
+            name: String::from("link"),
+            attrs: BTreeMap::from([(String::from("href"), href.to_string())]),
+        }
+    }
+
+    /// Whether the tag is a highlight of any colour. Highlights never overlap each other.
+    pub fn is_highlight(&self) -> bool {
+        HIGHLIGHT_COLOURS
+            .iter()
+            .any(|colour| colour.name == self.name)
+    }
+
+    pub fn href(&self) -> Option<&str> {
+        self.attrs.get("href").map(|href| href.as_str())
+    }
+
+    /// The name of the `TextTag` in a buffer. A link gets one `TextTag` per href, named
+    /// `link:<href>`, as a `TextTag` can not carry data of its own.
+    pub fn buffer_tag_name(&self) -> String {
+        match self.href() {
+            Some(href) if self.name == "link" => format!("link:{}", href),
+            _ => self.name.clone(),
+        }
+    }
+
+    pub fn from_buffer_tag_name(buffer_tag_name: &str, start: i32, end: i32) -> Self {
+        match buffer_tag_name.strip_prefix("link:") {
+            Some(href) => Self::link(start, end, href),
+            None => Self {
+                start,
+                end,
+                name: buffer_tag_name.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Applies the tag to a buffer, creating the `TextTag` of a link on first use.
+    pub fn apply(&self, text_buffer: &TextBuffer, start: &TextIter, end: &TextIter) {
+        let name = self.buffer_tag_name();
+        if self.name == "link" && text_buffer.tag_table().lookup(&name).is_none() {
+            text_buffer.create_tag(
+                Some(&name),
+                &[
+                    ("underline", &pango::Underline::Single),
+                    ("foreground", &"#1A5FB4"),
+                ],
+            );
+        }
+        text_buffer.apply_tag_by_name(&name, start, end);
+    }
+}
+
+/// Whether the text is a single URL, as pasted over a selection to make it a link.
+pub fn is_url(text: &str) -> bool {
+    let text = text.trim();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| text.len() > scheme.len() && text.starts_with(scheme))
+        && !text.contains(char::is_whitespace)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,6 +230,50 @@ pub struct TextWithTags {
     pub tags: Vec<Tag>,
 }
 
+/// A problem found by `TextWithTags::validate`, together with how it was repaired.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagProblem {
+    /// The tag name is unknown, the tag was dropped.
+    UnknownName(String),
+    /// The range reached outside of the text and was clamped to it.
+    OutOfRange { name: String, start: i32, end: i32 },
+    /// The range lay entirely outside of the text, the tag was dropped.
+    OutsideText { name: String, start: i32, end: i32 },
+    /// The range was empty or inverted, the tag was dropped.
+    EmptyRange { name: String, start: i32, end: i32 },
+    /// The same tag appeared more than once, the copies were dropped.
+    Duplicate { name: String, start: i32, end: i32 },
+    /// A required attribute, such as the `href` of a link, is missing, the tag was dropped.
+    MissingAttribute { name: String, attribute: String },
+}
+
+impl std::fmt::Display for TagProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagProblem::UnknownName(name) => write!(f, "unknown tag `{}` dropped", name),
+            TagProblem::OutOfRange { name, start, end } => {
+                write!(f, "tag `{}` {}..{} clamped to the text", name, start, end)
+            }
+            TagProblem::OutsideText { name, start, end } => {
+                write!(
+                    f,
+                    "tag `{}` {}..{} outside of the text dropped",
+                    name, start, end
+                )
+            }
+            TagProblem::EmptyRange { name, start, end } => {
+                write!(f, "empty tag `{}` {}..{} dropped", name, start, end)
+            }
+            TagProblem::Duplicate { name, start, end } => {
+                write!(f, "duplicate tag `{}` {}..{} dropped", name, start, end)
+            }
+            TagProblem::MissingAttribute { name, attribute } => {
+                write!(f, "tag `{}` without `{}` dropped", name, attribute)
+            }
+        }
+    }
+}
+
 impl Default for TextWithTags {
     fn default() -> Self {
         Self {
@@ -30,100 +290,848 @@ impl TextWithTags {
             tags: vec![],
         }
     }
-    pub fn as_text_buffer(&self) -> TextBuffer {
-        let text_buffer = TextBuffer::new(None);
-        text_buffer.create_tag(Some("highlight"), &[("background", &"#FEF3AC")]);
-        text_buffer.create_tag(Some("bold"), &[("weight", &800)]);
-        text_buffer.set_text(&self.text);
 
-        for tag in self.tags.iter() {
-            text_buffer.apply_tag_by_name(
-                tag.name.as_str(),
-                &text_buffer.iter_at_offset(tag.start),
-                &text_buffer.iter_at_offset(tag.end),
-            );
+    /// Repairs tags that can not be applied as they are, e.g. from a hand-edited or truncated
+    /// `notes.json`: unknown names, empty or inverted ranges and ranges entirely outside of the
+    /// text are dropped, other ranges are clamped to the text and duplicates are removed.
+    /// Returns what was repaired.
+    pub fn validate(&mut self) -> Vec<TagProblem> {
+        let len = self.text.chars().count() as i32;
+        let mut problems = vec![];
+        let mut tags: Vec<Tag> = vec![];
+
+        for mut tag in std::mem::take(&mut self.tags) {
+            if !TAG_NAMES.contains(&tag.name.as_str()) {
+                problems.push(TagProblem::UnknownName(tag.name));
+                continue;
+            }
+
+            if tag.name == "link" && tag.href().is_none() {
+                problems.push(TagProblem::MissingAttribute {
+                    name: tag.name,
+                    attribute: String::from("href"),
+                });
+                continue;
+            }
+
+            if tag.start >= tag.end {
+                problems.push(TagProblem::EmptyRange {
+                    name: tag.name,
+                    start: tag.start,
+                    end: tag.end,
+                });
+                continue;
+            }
+
+            let (start, end) = (tag.start.clamp(0, len), tag.end.clamp(0, len));
+            if start == end {
+                problems.push(TagProblem::OutsideText {
+                    name: tag.name,
+                    start: tag.start,
+                    end: tag.end,
+                });
+                continue;
+            }
+            if (start, end) != (tag.start, tag.end) {
+                problems.push(TagProblem::OutOfRange {
+                    name: tag.name.clone(),
+                    start: tag.start,
+                    end: tag.end,
+                });
+                tag.start = start;
+                tag.end = end;
+            }
+
+            if tags.contains(&tag) {
+                problems.push(TagProblem::Duplicate {
+                    name: tag.name,
+                    start: tag.start,
+                    end: tag.end,
+                });
+                continue;
+            }
+
+            tags.push(tag);
         }
 
+        self.tags = tags;
+        problems
+    }
+
+    /// Merges overlapping and adjacent tags with the same name and attributes into one, drops
+    /// empty ones, cuts apart highlights of different colours and links to different hrefs and
+    /// sorts the rest by position, so that e.g. bold applied twice over overlapping selections is
+    /// saved and rendered as a single `**a b**`.
+    pub fn normalize(&mut self) {
+        normalize_tags(&mut self.tags);
+    }
+
+    pub fn create_tags(text_buffer: &TextBuffer) {
+        for colour in HIGHLIGHT_COLOURS {
+            text_buffer.create_tag(Some(colour.name), &[("background", &colour.background)]);
+        }
+        text_buffer.create_tag(Some("bold"), &[("weight", &800)]);
+        text_buffer.create_tag(Some("italic"), &[("style", &pango::Style::Italic)]);
+        text_buffer.create_tag(Some("strikethrough"), &[("strikethrough", &true)]);
+        text_buffer.create_tag(
+            Some("underline"),
+            &[("underline", &pango::Underline::Single)],
+        );
+        text_buffer.create_tag(Some(HASHTAG_TAG), &[("foreground", &"#0B6E99")]);
+        text_buffer.create_tag(
+            Some(PAGE_REF_TAG),
+            &[("foreground", &"#0B6E99"), ("weight", &600)],
+        );
+        text_buffer.create_tag(
+            Some(TASK_DONE_TAG),
+            &[("strikethrough", &true), ("foreground", &"#8E8E8E")],
+        );
+        for kind in TokenKind::ALL {
+            text_buffer.create_tag(Some(kind.tag_name()), &[("foreground", &kind.foreground())]);
+        }
+        text_buffer.create_tag(
+            Some("code"),
+            &[("family", &"monospace"), ("background", &"#EDEDED")],
+        );
+    }
+
+    pub fn as_text_buffer(&self) -> TextBuffer {
+        let text_buffer = TextBuffer::new(None);
+        Self::create_tags(&text_buffer);
+        Document::from(self.clone()).write_to(&text_buffer);
         text_buffer
     }
 
+    /// Renders the text as Markdown. Markers are always properly nested: when tags overlap, the
+    /// inner marker is closed and reopened around the outer one, e.g. `**a==b==**==c==`.
+    /// Markers are moved off surrounding whitespace, which Markdown would not read as emphasis.
+    /// Marker characters of the text itself are escaped with a backslash, e.g. `a\*b\*c`.
     pub fn clipboard_text(&self) -> String {
-        let mut text_parts = vec![];
-        let mut last_offset = 0;
+        #[derive(Clone, Copy)]
+        struct Run<'a> {
+            marker: &'static MarkdownMarker,
+            href: Option<&'a str>,
+            start: usize,
+            end: usize,
+            /// Where the opening marker was written in the Markdown.
+            opened_at: usize,
+        }
+
+        impl Run<'_> {
+            fn close(&self, markdown: &mut String) {
+                match self.href {
+                    Some(href) => {
+                        markdown.push_str(&format!(
+                            "{}({})",
+                            self.marker.close,
+                            markdown_href(href)
+                        ));
+                    }
+                    // a code span holding backticks is fenced by a longer run of them, with a
+                    // space against backticks at its ends, e.g. ``` `` `a` `` ```
+                    None if self.marker.name == "code" => {
+                        let content = markdown.split_off(self.opened_at + self.marker.open.len());
+                        let longest = content.split(|c| c != '`').map(str::len).max();
+                        let fence = "`".repeat(longest.unwrap_or(0) + 1);
+                        let pad = if content.starts_with('`') || content.ends_with('`') {
+                            " "
+                        } else {
+                            ""
+                        };
+                        markdown.truncate(self.opened_at);
+                        markdown.push_str(&format!("{fence}{pad}{content}{pad}{fence}"));
+                    }
+                    None => markdown.push_str(self.marker.close),
+                }
+            }
+        }
 
         let chars = self.text.chars().collect::<Vec<_>>();
+        let is_space = |offset: usize| chars[offset].is_whitespace();
+        let clamp = |offset: i32| offset.clamp(0, chars.len() as i32) as usize;
 
-        for offset in 0..chars.len() + 1 {
-            let tag_parts: Vec<_> = self
-                .tags
+        let mut tags = self.tags.clone();
+        normalize_tags(&mut tags);
+
+        // tags still to be opened
+        let mut pending: Vec<Run> = vec![];
+
+        for marker in MARKDOWN_MARKERS.iter() {
+            if Self::markdown_marker(marker.name) != Some(marker) {
+                continue;
+            }
+
+            let runs = tags
                 .iter()
-                .filter(|tag| tag.start == offset as i32 || tag.end == offset as i32)
-                .filter_map(|t| match t.name.as_str() {
-                    "bold" => Some("**".to_string()),
-                    "highlight" => Some("==".to_string()),
-                    _ => None,
+                .filter(|tag| tag.name == marker.name)
+                .filter(|tag| marker.name != "link" || tag.href().is_some())
+                .map(|tag| Run {
+                    marker,
+                    href: tag.href(),
+                    start: clamp(tag.start),
+                    end: clamp(tag.end),
+                    opened_at: 0,
                 })
-                .collect();
+                .collect::<Vec<_>>();
+
+            for mut run in runs {
+                while run.start < run.end && is_space(run.start) {
+                    run.start += 1;
+                }
+                while run.end > run.start && is_space(run.end - 1) {
+                    run.end -= 1;
+                }
+                if run.start < run.end {
+                    pending.push(run);
+                }
+            }
+        }
+
+        // the content of a code span is taken literally, so it can not be escaped
+        let push_text = |markdown: &mut String, stack: &[Run], range: Range<usize>| {
+            if stack.iter().any(|run| run.marker.name == "code") {
+                markdown.extend(&chars[range]);
+            } else {
+                let in_link = stack.iter().any(|run| run.marker.name == "link");
+                push_escaped(markdown, &chars, range, in_link);
+            }
+        };
 
-            if tag_parts.len() > 0 {
-                text_parts.push(chars[last_offset..offset].iter().collect::<String>());
-                text_parts.extend(tag_parts);
-                last_offset = offset;
+        let mut markdown = String::new();
+        // open tags from outermost to innermost
+        let mut stack: Vec<Run> = vec![];
+        let mut offset = 0;
+
+        loop {
+            let next = pending
+                .iter()
+                .map(|run| run.start)
+                .chain(stack.iter().map(|run| run.end))
+                .min();
+            let Some(next) = next else {
+                break;
+            };
+
+            push_text(&mut markdown, &stack, offset..next);
+            offset = next;
+
+            let mut opening = vec![];
+            pending.retain(|run| {
+                if run.start == offset {
+                    opening.push(*run);
+                }
+                run.start != offset
+            });
+
+            let mut cut = stack
+                .iter()
+                .position(|run| run.end == offset)
+                .unwrap_or(stack.len());
+            if !opening.is_empty() || cut < stack.len() {
+                // nothing can be nested in a code span, so it is closed on every change
+                if let Some(code) = stack.iter().position(|run| run.marker.name == "code") {
+                    cut = cut.min(code);
+                }
+            }
+
+            for run in stack[cut..].iter().rev() {
+                run.close(&mut markdown);
+            }
+
+            for mut run in stack.split_off(cut) {
+                if run.end == offset {
+                    continue;
+                }
+                // a marker can not be reopened in front of whitespace, so wait for the next word
+                run.start = (offset..run.end).find(|&i| !is_space(i)).unwrap_or(offset);
+                if run.start > offset {
+                    pending.push(run);
+                } else {
+                    opening.push(run);
+                }
+            }
+
+            opening.sort_by_key(|run| (run.marker.name == "code", std::cmp::Reverse(run.end)));
+            for mut run in opening {
+                run.opened_at = markdown.len();
+                markdown.push_str(run.marker.open);
+                stack.push(run);
             }
         }
-        text_parts.push(chars[last_offset..].iter().collect());
-        text_parts.join("")
+
+        push_text(&mut markdown, &stack, offset..chars.len());
+        markdown
+    }
+
+    fn markdown_marker(name: &str) -> Option<&'static MarkdownMarker> {
+        MARKDOWN_MARKERS.iter().find(|m| m.name == name)
     }
 
-    pub fn find_tag(tag: &TextTag, mut iter: TextIter, end: TextIter) -> Option<Tag> {
-        if !iter.starts_tag(Some(tag)) {
-            return None;
+    /// Splits a run of `*` into closing and opening bold/italic markers the way `clipboard_text`
+    /// writes them: every tag is closed or opened at most once, and a tag is only reopened when
+    /// another one is closed in the same run. Returns `None` when the run can not be read so.
+    fn split_star_run(
+        open: &[&'static MarkdownMarker],
+        len: usize,
+        can_open: bool,
+        can_close: bool,
+    ) -> Option<(Vec<&'static MarkdownMarker>, Vec<&'static MarkdownMarker>)> {
+        let stars = MARKDOWN_MARKERS
+            .iter()
+            .filter(|m| m.open.starts_with('*'))
+            .collect::<Vec<_>>();
+        let subset = |bits: usize| {
+            stars
+                .iter()
+                .enumerate()
+                .filter(move |(i, _)| bits & (1 << i) != 0)
+                .map(|(_, m)| *m)
+        };
+        let is_open = |marker: &MarkdownMarker| open.contains(&marker);
+
+        // prefer closing as many tags as possible
+        for closes in (0..1 << stars.len()).rev() {
+            for opens in 0..1 << stars.len() {
+                let closed = subset(closes).collect::<Vec<_>>();
+                let opened = subset(opens).collect::<Vec<_>>();
+                let size = closed.iter().map(|m| m.close.len()).sum::<usize>()
+                    + opened.iter().map(|m| m.open.len()).sum::<usize>();
+
+                let valid = size == len
+                    && (closed.is_empty() || can_close)
+                    && (opened.is_empty() || can_open)
+                    && closed.iter().all(|m| is_open(m))
+                    && opened.iter().all(|m| !is_open(m) || closed.contains(m))
+                    && (closes & opens == 0 || closes & !opens != 0);
+
+                if valid {
+                    return Some((closed, opened));
+                }
+            }
         }
-        let start = iter.offset();
-        while iter.offset() <= end.offset() {
-            if !iter.tags().iter().any(|t| t.name() == tag.name()) {
-                return Some(Tag {
-                    start,
-                    end: iter.offset(),
-                    name: tag.name().unwrap().to_string(),
-                });
+
+        None
+    }
+
+    /// Parses the Markdown produced by `clipboard_text` (or copied from Logseq) back into text
+    /// and tags. Like Markdown emphasis, a marker only opens before and closes after a
+    /// non-whitespace character; markers without a counterpart are kept as literal text.
+    /// The content of a code span is taken literally and `[text](href)` becomes a link.
+    /// Elsewhere a backslash before a punctuation character makes it literal text.
+    pub fn from_markdown(markdown: &str) -> Self {
+        struct Marker {
+            name: &'static str,
+            marker: String,
+            href: Option<String>,
+            paired: bool,
+        }
+
+        enum Token {
+            Char(char),
+            Marker(Marker),
+        }
+
+        // unpaired opening markers as (marker, token index)
+        type Open = Vec<(&'static MarkdownMarker, usize)>;
+
+        fn open_tag(tokens: &mut Vec<Token>, open: &mut Open, marker: &'static MarkdownMarker) {
+            open.push((marker, tokens.len()));
+            tokens.push(Token::Marker(Marker {
+                name: marker.name,
+                marker: marker.open.to_string(),
+                href: None,
+                paired: false,
+            }));
+        }
+
+        fn close_tag(
+            tokens: &mut Vec<Token>,
+            open: &mut Open,
+            marker: &'static MarkdownMarker,
+            href: Option<String>,
+        ) {
+            let position = open.iter().rposition(|(m, _)| *m == marker);
+            let (_, open_index) = open.remove(position.unwrap());
+            if let Token::Marker(m) = &mut tokens[open_index] {
+                m.paired = true;
             }
-            iter.forward_char();
+            tokens.push(Token::Marker(Marker {
+                name: marker.name,
+                marker: match &href {
+                    Some(href) => format!("{}({})", marker.close, href),
+                    None => marker.close.to_string(),
+                },
+                href,
+                paired: true,
+            }));
         }
 
-        Some(Tag {
-            start,
-            end: end.offset(),
-            name: tag.name().unwrap().to_string(),
-        })
+        let link = Self::markdown_marker("link").unwrap();
+
+        let chars = markdown.chars().collect::<Vec<_>>();
+        let mut tokens = vec![];
+        let mut open: Open = vec![];
+        // the link being read, as returned by `find_link`
+        let mut link_end: Option<(usize, usize, String)> = None;
+        let mut offset = 0;
+
+        while offset < chars.len() {
+            match link_end.take() {
+                Some((end, next, href)) if end == offset => {
+                    if open.iter().any(|(m, _)| *m == link) {
+                        close_tag(&mut tokens, &mut open, link, Some(href));
+                        offset = next;
+                        continue;
+                    }
+                }
+                // e.g. the `]` was part of a code span
+                Some((end, ..)) if end < offset => {}
+                link => link_end = link,
+            }
+
+            if is_escape(&chars, offset) {
+                tokens.push(Token::Char(chars[offset + 1]));
+                offset += 2;
+                continue;
+            }
+
+            if chars[offset] == '['
+                && link_end.is_none()
+                && let Some(end) = find_link(&chars, offset)
+            {
+                open_tag(&mut tokens, &mut open, link);
+                link_end = Some(end);
+                offset += 1;
+                continue;
+            }
+
+            let flanking = |marker: &MarkdownMarker, len: usize| {
+                let before = offset.checked_sub(1).map(|i| chars[i]);
+                let after = chars.get(offset + len).copied();
+                let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric());
+                let can_open = after.is_some_and(|c| !c.is_whitespace())
+                    && (marker.intraword || !word(before));
+                let can_close = before.is_some_and(|c| !c.is_whitespace())
+                    && (marker.intraword || !word(after));
+                (can_open, can_close)
+            };
+
+            if chars[offset] == '*' {
+                let len = chars[offset..].iter().take_while(|c| **c == '*').count();
+                let (can_open, can_close) = flanking(&MARKDOWN_MARKERS[0], len);
+                let open_markers = open.iter().map(|(m, _)| *m).collect::<Vec<_>>();
+
+                if let Some((closed, opened)) =
+                    Self::split_star_run(&open_markers, len, can_open, can_close)
+                {
+                    for marker in closed {
+                        close_tag(&mut tokens, &mut open, marker, None);
+                    }
+                    for marker in opened {
+                        open_tag(&mut tokens, &mut open, marker);
+                    }
+                    offset += len;
+                    continue;
+                }
+            }
+
+            // closing an open tag takes precedence, so `*a***b**` closes the italic first
+            let closing = MARKDOWN_MARKERS.iter().find(|m| {
+                *m != link
+                    && matches(&chars, offset, m.close)
+                    && open.iter().any(|(marker, _)| marker == m)
+                    && flanking(m, m.close.chars().count()).1
+            });
+
+            // markers sharing a closing marker, like the highlight colours, close the latest one
+            let closing = closing.map(|marker| {
+                open.iter()
+                    .rev()
+                    .map(|(m, _)| *m)
+                    .find(|m| m.close == marker.close)
+                    .unwrap_or(marker)
+            });
+
+            if let Some(marker) = closing {
+                close_tag(&mut tokens, &mut open, marker, None);
+                offset += marker.close.chars().count();
+                continue;
+            }
+
+            let opening = MARKDOWN_MARKERS.iter().find(|m| {
+                *m != link
+                    && matches(&chars, offset, m.open)
+                    && flanking(m, m.open.chars().count()).0
+            });
+
+            match opening {
+                Some(marker) if marker.name == "code" => {
+                    let content =
+                        offset + chars[offset..].iter().take_while(|c| **c == '`').count();
+                    let fence = "`".repeat(content - offset);
+                    let len = fence.len();
+                    let end = find_code_span(&chars, offset);
+
+                    match end {
+                        Some(end) => {
+                            // a space against the fence on both ends is padding
+                            let mut inner = content..end;
+                            if inner.len() > 2
+                                && chars[content] == ' '
+                                && chars[end - 1] == ' '
+                                && chars[inner.clone()].iter().any(|c| *c != ' ')
+                            {
+                                inner = content + 1..end - 1;
+                            }
+                            tokens.push(Token::Marker(Marker {
+                                name: marker.name,
+                                marker: fence.clone(),
+                                href: None,
+                                paired: true,
+                            }));
+                            tokens.extend(chars[inner].iter().map(|c| Token::Char(*c)));
+                            tokens.push(Token::Marker(Marker {
+                                name: marker.name,
+                                marker: fence,
+                                href: None,
+                                paired: true,
+                            }));
+                            offset = end + len;
+                        }
+                        _ => {
+                            tokens.extend(chars[offset..content].iter().map(|c| Token::Char(*c)));
+                            offset = content;
+                        }
+                    }
+                }
+                Some(marker) => {
+                    open_tag(&mut tokens, &mut open, marker);
+                    offset += marker.open.chars().count();
+                }
+                None => {
+                    tokens.push(Token::Char(chars[offset]));
+                    offset += 1;
+                }
+            }
+        }
+
+        let mut text = String::new();
+        let mut len = 0;
+        let mut tags = vec![];
+        let mut starts: Vec<(&str, &str, i32)> = vec![];
+
+        for token in tokens.iter() {
+            match token {
+                Token::Char(c) => {
+                    text.push(*c);
+                    len += 1;
+                }
+                Token::Marker(Marker { marker, paired, .. }) if !paired => {
+                    text.push_str(marker);
+                    len += marker.chars().count() as i32;
+                }
+                Token::Marker(Marker {
+                    name, marker, href, ..
+                }) => {
+                    if let Some(position) = starts.iter().rposition(|(n, ..)| n == name) {
+                        let (name, open_marker, start) = starts.remove(position);
+                        if start == len {
+                            // an empty span like `****` is not a tag
+                            text.push_str(open_marker);
+                            text.push_str(marker);
+                            len += (open_marker.chars().count() + marker.chars().count()) as i32;
+                        } else if let Some(href) = href {
+                            tags.push(Tag::link(start, len, href));
+                        } else {
+                            tags.push(Tag {
+                                start,
+                                end: len,
+                                name: name.to_string(),
+                                ..Default::default()
+                            });
+                        }
+                    } else {
+                        starts.push((name, marker, len));
+                    }
+                }
+            }
+        }
+
+        Self { text, tags }
     }
 
-    pub fn from(text_buffer: &TextBuffer, mut start: TextIter, end: TextIter) -> Self {
+    /// Reads the text and tags between `start` and `end`. Jumps from one tag toggle to the
+    /// next, so the cost depends on the number of tag boundaries and not on the text length.
+    pub fn from(text_buffer: &TextBuffer, start: TextIter, end: TextIter) -> Self {
         let text = text_buffer.text(&start, &end, false).to_string();
-        let start_offset = start.offset();
+        let (start_offset, end_offset) = (start.offset(), end.offset());
         let mut tags = Vec::new();
 
-        while start.offset() < end.offset() {
-            for tag in start
-                .tags()
-                .iter()
-                .filter(|&tag| start.starts_tag(Some(tag)))
-            {
-                if let Some(tag) = Self::find_tag(tag, start.clone(), end.clone()) {
-                    tags.push(Tag {
-                        start: tag.start - start_offset,
-                        end: tag.end - start_offset,
-                        name: tag.name,
-                    });
+        let tag = |text_tag: &TextTag, start: i32, end: i32| {
+            Tag::from_buffer_tag_name(
+                &text_tag.name().unwrap_or_default(),
+                start - start_offset,
+                end - start_offset,
+            )
+        };
+
+        let mut open: Vec<(TextTag, i32)> = start
+            .tags()
+            .into_iter()
+            .map(|text_tag| (text_tag, start_offset))
+            .collect();
+
+        let mut iter = start;
+        while iter.forward_to_tag_toggle(None::<&TextTag>) && iter.offset() < end_offset {
+            let offset = iter.offset();
+            for text_tag in iter.toggled_tags(false) {
+                if let Some(index) = open.iter().position(|(t, _)| *t == text_tag) {
+                    let (text_tag, start) = open.remove(index);
+                    tags.push(tag(&text_tag, start, offset));
                 }
             }
-            start.forward_char();
+            for text_tag in iter.toggled_tags(true) {
+                open.push((text_tag, offset));
+            }
         }
 
+        for (text_tag, start) in open {
+            if start < end_offset {
+                tags.push(tag(&text_tag, start, end_offset));
+            }
+        }
+        // tags only used to draw the buffer, like the ones of done tasks, page references or
+        // hashtags, are not kept
+        tags.retain(|tag| TAG_NAMES.contains(&tag.name.as_str()));
+        tags.sort_by_key(|tag| (tag.start, tag.end));
+
         Self { text, tags }
     }
 }
 
+fn matches(chars: &[char], offset: usize, marker: &str) -> bool {
+    let len = marker.chars().count();
+    offset + len <= chars.len()
+        && marker
+            .chars()
+            .eq(chars[offset..offset + len].iter().cloned())
+}
+
+/// Whether the character at `offset` is a backslash escaping the punctuation after it, e.g.
+/// `\*` for a literal `*`.
+fn is_escape(chars: &[char], offset: usize) -> bool {
+    chars[offset] == '\\'
+        && chars
+            .get(offset + 1)
+            .is_some_and(char::is_ascii_punctuation)
+}
+
+/// Whether the character at `offset` is escaped by a backslash before it.
+fn escaped(chars: &[char], offset: usize) -> bool {
+    offset > 0 && is_escape(chars, offset - 1) && !(offset > 1 && escaped(chars, offset - 1))
+}
+
+/// Writes `chars[range]` as Markdown text, escaping the characters that would otherwise be read
+/// as a marker, e.g. `a\*b\*c`. `in_link` is set inside the text of a `[text](href)`.
+fn push_escaped(markdown: &mut String, chars: &[char], range: Range<usize>, in_link: bool) {
+    let word = |offset: Option<usize>| offset.is_some_and(|i| chars[i].is_alphanumeric());
+    let written_before = markdown.chars().next_back();
+
+    for i in range.clone() {
+        let c = chars[i];
+        // the neighbours in the Markdown, a marker may be written at the ends of the range
+        let before = (i > range.start).then(|| i - 1);
+        let after = (i + 1 < range.end).then_some(i + 1);
+        let escape = match c {
+            '*' | '`' => true,
+            '\\' => after.is_none_or(|i| chars[i].is_ascii_punctuation()),
+            '_' => !word(before) || !word(after),
+            '=' | '~' => {
+                before.is_none_or(|i| chars[i] == c) || after.is_none_or(|i| chars[i] == c)
+            }
+            '<' => matches(chars, i + 1, "u>") || matches(chars, i + 1, "/u>"),
+            '[' => {
+                // only the `[[` of a `[[page]]` reference outside of a link is kept as it is
+                let is_bracket = |j: &usize| chars[*j] == '[';
+                let first = i - (range.start..i).rev().take_while(is_bracket).count();
+                let end = i + (i..range.end).take_while(is_bracket).count();
+                let closed = (end..range.end).any(|j| matches(chars, j, "]]"));
+                in_link
+                    || end - first != 2
+                    || !closed
+                    || (first == range.start && written_before == Some('['))
+            }
+            ']' => in_link,
+            _ => false,
+        };
+        if escape {
+            markdown.push('\\');
+        }
+        markdown.push(c);
+    }
+}
+
+/// Finds the closing fence of the code span opened by the backticks at `offset`, a run of as
+/// many backticks as it was opened with.
+fn find_code_span(chars: &[char], offset: usize) -> Option<usize> {
+    let len = chars[offset..].iter().take_while(|c| **c == '`').count();
+    let fence = "`".repeat(len);
+    (offset + len + 1..chars.len()).find(|&i| {
+        matches(chars, i, &fence) && chars[i - 1] != '`' && chars.get(i + len) != Some(&'`')
+    })
+}
+
+/// Finds the end of `[text](href)` starting at `offset`, as (offset of `]`, offset after
+/// `)`, href). `[[page]]` references are left alone, also inside of the text.
+fn find_link(chars: &[char], offset: usize) -> Option<(usize, usize, String)> {
+    // the first `[` of `[[[$red]]==a==](href)` opens a link, the one of `[[page]]` does not
+    let opens_reference = matches(chars, offset + 1, "[") && !matches(chars, offset + 2, "[");
+    let after_bracket = offset > 0 && chars[offset - 1] == '[' && !escaped(chars, offset - 1);
+    if opens_reference || after_bracket {
+        return None;
+    }
+    let mut end = offset + 1;
+    while end < chars.len() && chars[end] != ']' {
+        if is_escape(chars, end) {
+            end += 1;
+        } else if chars[end] == '`' {
+            // a `]` in a code span does not end the text
+            let fence = chars[end..].iter().take_while(|c| **c == '`').count();
+            end = find_code_span(chars, end).map_or(end, |close| close) + fence - 1;
+        } else if matches(chars, end, "[[") {
+            end = (end + 2..chars.len()).find(|&i| matches(chars, i, "]]"))? + 1;
+        }
+        end += 1;
+    }
+    if end >= chars.len() || end == offset + 1 || chars.get(end + 1) != Some(&'(') {
+        return None;
+    }
+    // parentheses in the href are balanced, e.g. `wiki/Rust_(programming_language)`
+    let mut depth = 0;
+    let close = (end + 2..chars.len()).find(|&i| match chars[i] {
+        '(' => {
+            depth += 1;
+            false
+        }
+        ')' if depth > 0 => {
+            depth -= 1;
+            false
+        }
+        c => c == ')' || c.is_whitespace(),
+    })?;
+    if chars[close] != ')' || close == end + 2 {
+        return None;
+    }
+    Some((end, close + 1, chars[end + 2..close].iter().collect()))
+}
+
+/// The href as written in `[text](href)`: parentheses that are not balanced would end it early
+/// or never, so they are percent-encoded.
+fn markdown_href(href: &str) -> std::borrow::Cow<'_, str> {
+    let mut depth = 0;
+    let balanced = href.chars().all(|c| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        depth >= 0
+    }) && depth == 0;
+
+    if balanced {
+        href.into()
+    } else {
+        href.replace('(', "%28").replace(')', "%29").into()
+    }
+}
+
+/// See `TextWithTags::normalize`.
+pub(crate) fn normalize_tags(tags: &mut Vec<Tag>) {
+    tags.retain(|tag| tag.start < tag.end);
+    tags.sort_by(|a, b| {
+        (&a.name, &a.attrs, a.start, a.end).cmp(&(&b.name, &b.attrs, b.start, b.end))
+    });
+
+    let mut merged: Vec<Tag> = vec![];
+    for tag in std::mem::take(tags) {
+        match merged.last_mut() {
+            Some(last)
+                if last.name == tag.name && last.attrs == tag.attrs && tag.start <= last.end =>
+            {
+                last.end = last.end.max(tag.end);
+            }
+            _ => merged.push(tag),
+        }
+    }
+
+    merged.sort_by_key(|tag| (tag.start, tag.end));
+
+    // neither highlights nor links can be nested, one overlapping an earlier one starts after it
+    let (mut highlight_end, mut link_end) = (0, 0);
+    merged.retain_mut(|tag| {
+        let last_end = if tag.is_highlight() {
+            &mut highlight_end
+        } else if tag.name == "link" {
+            &mut link_end
+        } else {
+            return true;
+        };
+        tag.start = tag.start.max(*last_end);
+        *last_end = (*last_end).max(tag.end);
+        tag.start < tag.end
+    });
+
+    *tags = merged;
+}
+
+/// `gtk::TextBuffer` side of `Document`, the buffer holds the text being edited while the
+/// document holds it for everything else. Edits read and write back only the range they change,
+/// e.g. the selection, so that their cost does not grow with the rest of the block.
+impl Document {
+    /// Reads the text and tags between `start` and `end` of a buffer, with offsets from `start`.
+    pub fn from_text_buffer_range(
+        text_buffer: &TextBuffer,
+        start: &TextIter,
+        end: &TextIter,
+    ) -> Self {
+        TextWithTags::from(text_buffer, *start, *end).into()
+    }
+
+    /// Replaces the content of a buffer with the document.
+    pub fn write_to(&self, text_buffer: &TextBuffer) {
+        text_buffer.set_text(self.text());
+        self.write_tags_to(text_buffer);
+    }
+
+    /// Replaces the tags of a buffer holding the same text with the ones of the document.
+    pub fn write_tags_to(&self, text_buffer: &TextBuffer) {
+        self.write_tags_to_range(text_buffer, 0);
+    }
+
+    /// Replaces the tags of a buffer holding the text of the document at `offset` with the ones
+    /// of the document, leaving the rest of the buffer as it is.
+    pub fn write_tags_to_range(&self, text_buffer: &TextBuffer, offset: i32) {
+        let start = text_buffer.iter_at_offset(offset);
+        let end = text_buffer.iter_at_offset(offset + self.char_count());
+        text_buffer.tag_table().foreach(|tag| {
+            let is_document_tag = tag.name().is_some_and(|name| {
+                TAG_NAMES.contains(&Tag::from_buffer_tag_name(&name, 0, 0).name.as_str())
+            });
+            if is_document_tag {
+                text_buffer.remove_tag(tag, &start, &end);
+            }
+        });
+
+        for tag in self.tags() {
+            tag.apply(
+                text_buffer,
+                &text_buffer.iter_at_offset(offset + tag.start),
+                &text_buffer.iter_at_offset(offset + tag.end),
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -137,6 +1145,7 @@ mod tests {
                 start: 0,
                 end: 4,
                 name: String::from("bold"),
+                ..Default::default()
             }],
         };
         assert_eq!(text_with_tags.clipboard_text(), "**Bold** text here");
@@ -148,6 +1157,7 @@ mod tests {
                 start: 5,
                 end: 16,
                 name: String::from("highlight"),
+                ..Default::default()
             }],
         };
         assert_eq!(
@@ -163,11 +1173,13 @@ mod tests {
                     start: 0,
                     end: 4,
                     name: String::from("bold"),
+                    ..Default::default()
                 },
                 Tag {
                     start: 9,
                     end: 20,
                     name: String::from("highlight"),
+                    ..Default::default()
                 },
             ],
         };
@@ -176,7 +1188,7 @@ mod tests {
             "**Bold** and ==highlighted=="
         );
 
-        // Test case 4: Overlapping tags (should be ignored)
+        // Test case 4: Overlapping tags are closed and reopened to stay nested
         let text_with_tags = TextWithTags {
             text: String::from("Overlapping tags"),
             tags: vec![
@@ -184,15 +1196,20 @@ mod tests {
                     start: 0,
                     end: 10,
                     name: String::from("bold"),
+                    ..Default::default()
                 },
                 Tag {
                     start: 5,
                     end: 15,
                     name: String::from("highlight"),
+                    ..Default::default()
                 },
             ],
         };
-        assert_eq!(text_with_tags.clipboard_text(), "**Overl==appin**g tag==s");
+        assert_eq!(
+            text_with_tags.clipboard_text(),
+            "**Overl==appin==**==g tag==s"
+        );
 
         let text_with_tags = TextWithTags {
             text: String::from("한글 텍스트"),
@@ -200,8 +1217,472 @@ mod tests {
                 start: 1,
                 end: 2,
                 name: String::from("bold"),
+                ..Default::default()
             }],
         };
         assert_eq!(text_with_tags.clipboard_text(), "한**글** 텍스트");
     }
+
+    fn tag_tuples(text_with_tags: &TextWithTags) -> Vec<(i32, i32, &str)> {
+        text_with_tags
+            .tags
+            .iter()
+            .map(|t| (t.start, t.end, t.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_markdown() {
+        let text_with_tags = TextWithTags::from_markdown("**Bold** and ==highlighted==");
+        assert_eq!(text_with_tags.text, "Bold and highlighted");
+        assert_eq!(
+            tag_tuples(&text_with_tags),
+            vec![(0, 4, "bold"), (9, 20, "highlight")]
+        );
+
+        let text_with_tags = TextWithTags::from_markdown("한**글** 텍스트");
+        assert_eq!(text_with_tags.text, "한글 텍스트");
+        assert_eq!(tag_tuples(&text_with_tags), vec![(1, 2, "bold")]);
+
+        // Nested markers
+        let text_with_tags = TextWithTags::from_markdown("**a ==b== c**");
+        assert_eq!(text_with_tags.text, "a b c");
+        assert_eq!(
+            tag_tuples(&text_with_tags),
+            vec![(2, 3, "highlight"), (0, 5, "bold")]
+        );
+
+        // Unmatched markers are kept as they are
+        let text_with_tags = TextWithTags::from_markdown("2 ** 3 == 8 and **bold**");
+        assert_eq!(text_with_tags.text, "2 ** 3 == 8 and bold");
+        assert_eq!(tag_tuples(&text_with_tags), vec![(16, 20, "bold")]);
+
+        let text_with_tags = TextWithTags::from_markdown("a **** b");
+        assert_eq!(text_with_tags.text, "a **** b");
+        assert!(text_with_tags.tags.is_empty());
+    }
+
+    #[test]
+    fn test_from_markdown_inline_styles() {
+        let text_with_tags = TextWithTags::from_markdown("*it* _it_ ~~gone~~ `a*b*c` <u>under</u>");
+        assert_eq!(text_with_tags.text, "it it gone a*b*c under");
+        assert_eq!(
+            tag_tuples(&text_with_tags),
+            vec![
+                (0, 2, "italic"),
+                (3, 5, "italic"),
+                (6, 10, "strikethrough"),
+                (11, 16, "code"),
+                (17, 22, "underline")
+            ]
+        );
+
+        // `_` inside a word is not a marker
+        let text_with_tags = TextWithTags::from_markdown("snake_case_name");
+        assert_eq!(text_with_tags.text, "snake_case_name");
+        assert!(text_with_tags.tags.is_empty());
+
+        let text_with_tags = TextWithTags::from_markdown("***both***");
+        assert_eq!(text_with_tags.text, "both");
+        assert_eq!(
+            tag_tuples(&text_with_tags),
+            vec![(0, 4, "bold"), (0, 4, "italic")]
+        );
+
+        let text_with_tags = TextWithTags::from_markdown("*a***b**");
+        assert_eq!(text_with_tags.text, "ab");
+        assert_eq!(
+            tag_tuples(&text_with_tags),
+            vec![(0, 1, "italic"), (1, 2, "bold")]
+        );
+
+        // unterminated code span
+        let text_with_tags = TextWithTags::from_markdown("`a **b**");
+        assert_eq!(text_with_tags.text, "`a b");
+        assert_eq!(tag_tuples(&text_with_tags), vec![(3, 4, "bold")]);
+    }
+
+    #[test]
+    fn test_clipboard_text_nesting() {
+        let tag = |start, end, name: &str| Tag {
+            start,
+            end,
+            name: name.to_string(),
+            ..Default::default()
+        };
+
+        // Markers are moved off whitespace
+        let text_with_tags = TextWithTags {
+            text: String::from("a bold move"),
+            tags: vec![tag(1, 7, "bold")],
+        };
+        assert_eq!(text_with_tags.clipboard_text(), "a **bold** move");
+
+        // A reopened marker waits for the next word
+        let text_with_tags = TextWithTags {
+            text: String::from("one two three"),
+            tags: vec![tag(0, 3, "bold"), tag(2, 13, "highlight")],
+        };
+        assert_eq!(text_with_tags.clipboard_text(), "**on==e==** ==two three==");
+
+        // Overlapping and adjacent ranges of the same tag are written once
+        let text_with_tags = TextWithTags {
+            text: String::from("ab"),
+            tags: vec![tag(0, 1, "bold"), tag(1, 2, "bold"), tag(0, 2, "bold")],
+        };
+        assert_eq!(text_with_tags.clipboard_text(), "**ab**");
+
+        // Nothing is nested in a code span
+        let text_with_tags = TextWithTags {
+            text: String::from("let x"),
+            tags: vec![tag(0, 5, "code"), tag(4, 5, "bold")],
+        };
+        assert_eq!(text_with_tags.clipboard_text(), "`let `**`x`**");
+    }
+
+    #[test]
+    fn test_validate() {
+        let tag = |start, end, name: &str| Tag {
+            start,
+            end,
+            name: name.to_string(),
+            ..Default::default()
+        };
+
+        let mut text_with_tags = TextWithTags {
+            text: String::from("한글 text"),
+            tags: vec![
+                tag(0, 2, "bold"),
+                tag(0, 2, "bold"),
+                tag(1, 3, "blink"),
+                tag(3, 1, "italic"),
+                tag(-2, 1, "highlight"),
+                tag(5, 40, "code"),
+                tag(10, 12, "underline"),
+            ],
+        };
+
+        assert_eq!(
+            text_with_tags.validate(),
+            vec![
+                TagProblem::Duplicate {
+                    name: String::from("bold"),
+                    start: 0,
+                    end: 2
+                },
+                TagProblem::UnknownName(String::from("blink")),
+                TagProblem::EmptyRange {
+                    name: String::from("italic"),
+                    start: 3,
+                    end: 1
+                },
+                TagProblem::OutOfRange {
+                    name: String::from("highlight"),
+                    start: -2,
+                    end: 1
+                },
+                TagProblem::OutOfRange {
+                    name: String::from("code"),
+                    start: 5,
+                    end: 40
+                },
+                TagProblem::OutsideText {
+                    name: String::from("underline"),
+                    start: 10,
+                    end: 12
+                },
+            ]
+        );
+        assert_eq!(
+            tag_tuples(&text_with_tags),
+            vec![(0, 2, "bold"), (0, 1, "highlight"), (5, 7, "code")]
+        );
+
+        // valid tags are left alone
+        assert!(text_with_tags.validate().is_empty());
+    }
+
+    #[test]
+    fn test_highlight_colours() {
+        let text_with_tags = TextWithTags::from_markdown(
+            "==old== [[$red]]==urgent== [[$yellow]]==soon== [[$purple]]==a **b**== [[$blue]]c",
+        );
+        assert_eq!(text_with_tags.text, "old urgent soon a b [[$blue]]c");
+        assert_eq!(
+            tag_tuples(&text_with_tags),
+            vec![
+                (0, 3, "highlight"),
+                (4, 10, "highlight-red"),
+                (11, 15, "highlight"),
+                (18, 19, "bold"),
+                (16, 19, "highlight-purple"),
+            ]
+        );
+        assert_eq!(
+            text_with_tags.clipboard_text(),
+            "==old== [[$red]]==urgent== ==soon== [[$purple]]==a **b**== [[$blue]]c"
+        );
+
+        // a colour over another one cuts it
+        let mut document = Document::from(TextWithTags::from_markdown("[[$green]]==abcd=="));
+        document.apply_tag(Tag {
+            start: 1,
+            end: 3,
+            name: String::from("highlight-blue"),
+            ..Default::default()
+        });
+        let text_with_tags: TextWithTags = document.into();
+        assert_eq!(
+            text_with_tags.clipboard_text(),
+            "[[$green]]==a==[[$blue]]==bc==[[$green]]==d=="
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let tag = |start: i32, end: i32, name: &str| Tag {
+            start,
+            end,
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let mut text_with_tags = TextWithTags {
+            text: String::from("abcdefgh"),
+            tags: vec![
+                tag(4, 6, "bold"),
+                tag(0, 2, "bold"),
+                tag(2, 3, "bold"),
+                tag(1, 3, "bold"),
+                tag(2, 5, "highlight"),
+                tag(7, 7, "italic"),
+                Tag::link(5, 6, "https://a"),
+                Tag::link(6, 8, "https://a"),
+                Tag::link(6, 8, "https://b"),
+            ],
+        };
+
+        // split and adjacent ranges are written as one marker
+        assert_eq!(
+            text_with_tags.clipboard_text(),
+            "**ab==c==**==d**e**==[**f**gh](https://a)"
+        );
+
+        text_with_tags.normalize();
+        assert_eq!(
+            text_with_tags
+                .tags
+                .iter()
+                .map(|t| (t.start, t.end, t.name.as_str(), t.href()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 3, "bold", None),
+                (2, 5, "highlight", None),
+                (4, 6, "bold", None),
+                // links are not nested, the one to b is covered by the one to a
+                (5, 8, "link", Some("https://a")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_links() {
+        let text_with_tags = TextWithTags::from_markdown(
+            "see [the **docs**](https://relm4.org) or [[Relm4]] [not a link] (x)",
+        );
+        assert_eq!(
+            text_with_tags.text,
+            "see the docs or [[Relm4]] [not a link] (x)"
+        );
+        assert_eq!(
+            text_with_tags.tags,
+            vec![
+                Tag {
+                    start: 8,
+                    end: 12,
+                    name: String::from("bold"),
+                    ..Default::default()
+                },
+                Tag::link(4, 12, "https://relm4.org"),
+            ]
+        );
+        assert_eq!(
+            text_with_tags.clipboard_text(),
+            "see [the **docs**](https://relm4.org) or [[Relm4]] \\[not a link] (x)"
+        );
+
+        // overlapping links are not nested
+        let text_with_tags = TextWithTags {
+            text: String::from("one two"),
+            tags: vec![Tag::link(0, 5, "https://a"), Tag::link(2, 7, "https://b")],
+        };
+        assert_eq!(
+            text_with_tags.clipboard_text(),
+            "[one t](https://a)[wo](https://b)"
+        );
+
+        // parentheses in hrefs survive the round trip
+        for href in [
+            "https://en.wikipedia.org/wiki/Rust_(programming_language)",
+            "https://a.b/(x)(y)/z",
+            "https://a.b/smile:)",
+            "https://a.b/(open",
+        ] {
+            let text_with_tags = TextWithTags {
+                text: String::from("Rust (language)"),
+                tags: vec![Tag::link(0, 4, href)],
+            };
+            let read = TextWithTags::from_markdown(&text_with_tags.clipboard_text());
+            assert_eq!(read.text, "Rust (language)", "{}", href);
+            let read_href = read.tags[0].href().unwrap().replace("%28", "(");
+            assert_eq!(read_href.replace("%29", ")"), href);
+        }
+        assert_eq!(
+            TextWithTags {
+                text: String::from("Rust"),
+                tags: vec![Tag::link(
+                    0,
+                    4,
+                    "https://en.wikipedia.org/wiki/Rust_(programming_language)"
+                )],
+            }
+            .clipboard_text(),
+            "[Rust](https://en.wikipedia.org/wiki/Rust_(programming_language))"
+        );
+
+        let mut text_with_tags = TextWithTags {
+            text: String::from("link"),
+            tags: vec![Tag {
+                start: 0,
+                end: 4,
+                name: String::from("link"),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            text_with_tags.validate(),
+            vec![TagProblem::MissingAttribute {
+                name: String::from("link"),
+                attribute: String::from("href")
+            }]
+        );
+
+        assert!(is_url(" https://example.com/a?b=c\n"));
+        assert!(!is_url("https://"));
+        assert!(!is_url("see https://example.com"));
+    }
+
+    #[test]
+    fn test_markdown_round_trip() {
+        for markdown in [
+            "**Bold** and ==highlighted==",
+            "*italic*, ~~struck~~, `code` and <u>underlined</u>",
+        ] {
+            assert_eq!(
+                TextWithTags::from_markdown(markdown).clipboard_text(),
+                markdown
+            );
+        }
+    }
+
+    #[test]
+    fn test_escaped_markers() {
+        // marker characters in plain text are escaped, and read back as text
+        for (text, markdown) in [
+            ("a*b*c", "a\\*b\\*c"),
+            ("x = a==b==c", "x = a\\=\\=b\\=\\=c"),
+            ("snake_case `x` and _y_", "snake_case \\`x\\` and \\_y\\_"),
+            (
+                "[a](https://b) and [[page]]",
+                "\\[a](https://b) and [[page]]",
+            ),
+            ("~~gone~~ <u>not</u>", "\\~\\~gone\\~\\~ \\<u>not\\</u>"),
+            ("C:\\path\\*", "C:\\path\\\\\\*"),
+        ] {
+            let text_with_tags = TextWithTags::from_str(text);
+            assert_eq!(text_with_tags.clipboard_text(), markdown);
+
+            let parsed = TextWithTags::from_markdown(markdown);
+            assert_eq!(parsed.text, text);
+            assert!(parsed.tags.is_empty(), "{:?}", parsed.tags);
+        }
+
+        // a code span holding backticks is fenced by more of them
+        let text_with_tags = TextWithTags::from_markdown("run `` `ls` `` now");
+        assert_eq!(text_with_tags.text, "run `ls` now");
+        assert_eq!(text_with_tags.clipboard_text(), "run `` `ls` `` now");
+    }
+
+    mod round_trip {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn text_with_tags() -> impl Strategy<Value = TextWithTags> {
+            proptest::collection::vec(
+                proptest::sample::select(vec![
+                    'a', 'b', '글', ' ', '\n', '*', '=', '_', '`', '[', ']', '(', ')', '~', '<',
+                    '\\',
+                ]),
+                0..16,
+            )
+            .prop_flat_map(|chars| {
+                let len = chars.len() as i32;
+                let tag = (0..=len, 0..=len, proptest::sample::select(TAG_NAMES)).prop_map(
+                    |(a, b, name)| match name {
+                        "link" => Tag::link(a.min(b), a.max(b), "https://example.com/a_b"),
+                        _ => Tag {
+                            start: a.min(b),
+                            end: a.max(b),
+                            name: name.to_string(),
+                            ..Default::default()
+                        },
+                    },
+                );
+                (
+                    Just(chars.into_iter().collect::<String>()),
+                    proptest::collection::vec(tag, 0..6),
+                )
+            })
+            .prop_map(|(text, tags)| TextWithTags { text, tags })
+        }
+
+        /// The tags covering each non-whitespace character, which is what survives Markdown.
+        fn coverage(text_with_tags: &TextWithTags) -> Vec<Vec<String>> {
+            text_with_tags
+                .text
+                .chars()
+                .enumerate()
+                .filter(|(_, c)| !c.is_whitespace())
+                .map(|(offset, _)| {
+                    let offset = offset as i32;
+                    let mut names = text_with_tags
+                        .tags
+                        .iter()
+                        .filter(|t| t.start <= offset && offset < t.end)
+                        .map(|t| t.buffer_tag_name())
+                        .collect::<Vec<_>>();
+                    names.sort();
+                    names.dedup();
+                    names
+                })
+                .collect()
+        }
+
+        proptest! {
+            #[test]
+            fn clipboard_text_parses_back_to_the_same_spans(text_with_tags in text_with_tags()) {
+                let markdown = text_with_tags.clipboard_text();
+                let parsed = TextWithTags::from_markdown(&markdown);
+
+                prop_assert_eq!(&parsed.text, &text_with_tags.text, "markdown: {:?}", markdown);
+                let mut expected = text_with_tags.clone();
+                expected.normalize();
+                prop_assert_eq!(
+                    coverage(&parsed),
+                    coverage(&expected),
+                    "markdown: {:?}",
+                    markdown
+                );
+            }
+        }
+    }
 }
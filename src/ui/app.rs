@@ -1,24 +1,40 @@
-use crate::ui::{
-    APP_BROKER, RootMsg,
-    editor_box::EditorBox,
-    file_writer::{FileWriter, FileWriterMsg},
+use crate::{
+    fs::backup_path,
+    logseq,
+    notes::{self, Notes, NotesError},
+    settings::Settings,
+    text::Block,
+    ui::{
+        APP_BROKER, ExportTarget, RootMsg,
+        editor_box::{EditorBox, EditorMsg},
+        file_writer::{FileWriter, FileWriterMsg, Snapshot, retry_delay},
+    },
 };
-use gtk::{gdk, glib};
-use log::trace;
+use chrono::{DateTime, Utc};
+use gtk::{gdk, gio, glib};
+use log::{trace, warn};
 use relm4::{
     WorkerController,
     gtk::{CssProvider, prelude::*},
     prelude::*,
     tokio,
 };
-use std::path::PathBuf;
+use serde_json::{Map, Value};
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 pub struct App {
     editor_box: Controller<EditorBox>,
     file_writer: WorkerController<FileWriter>,
     save_file_path: PathBuf,
-    text_changed: bool,
-    editor_changed: bool,
+    save: SaveState,
+    status: String,
+    settings: Settings,
+    /// Settings of the pad saved in `notes.json`, written back as they were read.
+    notes_settings: Map<String, Value>,
+    filtering: bool,
 }
 
 #[relm4::component(pub)]
@@ -39,6 +55,27 @@ impl SimpleComponent for App {
             gtk::Box {
                 set_orientation: gtk::Orientation::Vertical,
 
+                #[name = "filter_bar"]
+                append = &gtk::SearchBar {
+                    #[watch]
+                    set_search_mode: model.filtering,
+                    connect_search_mode_enabled_notify[sender] => move |bar| {
+                        if !bar.is_search_mode() {
+                            sender.input(RootMsg::FilterChanged(None));
+                        }
+                    },
+
+                    #[wrap(Some)]
+                    #[name = "filter_entry"]
+                    set_child = &gtk::SearchEntry {
+                        set_placeholder_text: Some("#tags of the blocks to show"),
+                        set_hexpand: true,
+                        connect_search_changed[sender] => move |entry| {
+                            sender.input(RootMsg::FilterChanged(Some(entry.text().to_string())));
+                        },
+                    },
+                },
+
                 #[name = "ui"]
                 append = &gtk::ScrolledWindow {
                     set_vexpand: true,
@@ -58,7 +95,8 @@ impl SimpleComponent for App {
 
                 append = &gtk::Label {
                     add_css_class: "status-label",
-                    set_label: "",
+                    #[watch]
+                    set_label: &model.status,
                     set_size_request: (-1, 20),
                 }
             }
@@ -70,28 +108,58 @@ impl SimpleComponent for App {
         _root: Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
-        let file_path = dirs::home_dir().unwrap().join(".cache/illpad/notes.json");
+        let (file_path, no_home) = match dirs::home_dir() {
+            Some(home) => (home.join(".cache/illpad/notes.json"), false),
+            None => {
+                let dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+                (dir.join(".cache/illpad/notes.json"), true)
+            }
+        };
 
-        let mut text_with_tags = vec![];
+        let (mut blocks, notes_settings, mut status, read_only) = load_notes(&file_path);
+        if no_home {
+            let home_status = format!(
+                "No home directory, keeping the notes in {}",
+                file_path.display()
+            );
+            warn!("{}", home_status);
+            status = if status.is_empty() {
+                home_status
+            } else {
+                format!("{}. {}", home_status, status)
+            };
+        }
 
-        if file_path.exists() {
-            let text = std::fs::read_to_string(&file_path).unwrap();
-            if let Ok(loaded) = serde_json::from_str(&text) {
-                text_with_tags = loaded;
+        let mut repaired = 0;
+        for (index, block) in blocks.iter_mut().enumerate() {
+            for problem in block.content.validate() {
+                warn!("{}: block {}: {}", file_path.display(), index + 1, problem);
+                repaired += 1;
             }
+            block.content.normalize();
+        }
+        if repaired > 0 && status.is_empty() {
+            status = format!("Repaired {} broken tags in notes.json", repaired);
         }
 
         let model = App {
-            editor_box: EditorBox::builder().launch(text_with_tags).detach(),
+            editor_box: EditorBox::builder().launch(blocks).detach(),
             save_file_path: file_path.clone(),
             file_writer: FileWriter::builder().detach_worker(file_path).forward(
                 sender.input_sender(),
                 |msg| match msg {
                     FileWriterMsg::SaveComplete => RootMsg::SaveComplete,
+                    FileWriterMsg::SaveFailed(err) => RootMsg::SaveFailed(err),
                 },
             ),
-            text_changed: false,
-            editor_changed: false,
+            save: SaveState {
+                read_only,
+                ..Default::default()
+            },
+            status,
+            settings: Settings::load(),
+            notes_settings,
+            filtering: false,
         };
 
         sender.command(move |_out, shutdown| {
@@ -107,6 +175,7 @@ impl SimpleComponent for App {
         });
 
         let widgets = view_output!();
+        widgets.filter_bar.connect_entry(&widgets.filter_entry);
 
         // widgets.ui.vadjustment().connect_changed(|v| {
         //     if v.value() < v.upper() {
@@ -132,41 +201,348 @@ impl SimpleComponent for App {
         trace!("App Message received {:?}", msg);
         match msg {
             RootMsg::TextChanged => {
-                self.text_changed = true;
+                self.save.text_changed = true;
             }
             RootMsg::EditorChanged => {
-                self.editor_changed = true;
+                self.save.editor_changed = true;
+            }
+            RootMsg::SaveComplete => {
+                self.save.saving = false;
+                if self.save.error.take().is_some() {
+                    self.status = String::from("Saved notes.json");
+                }
+                self.save.failures = 0;
+                self.save.retry = None;
+            }
+            RootMsg::SaveFailed(err) => {
+                self.save.saving = false;
+                self.save.failures += 1;
+                let delay = retry_delay(self.save.failures);
+                self.save.retry = Some(Instant::now() + delay);
+                self.status = format!(
+                    "Failed to save notes.json: {}, retrying in {} s",
+                    err,
+                    delay.as_secs()
+                );
+                self.save.error = Some(err);
             }
-            RootMsg::SaveComplete => {}
             RootMsg::ExitTriggered => {
-                if self.text_changed || self.editor_changed {
-                    FileWriter::save(
-                        &self.save_file_path,
-                        self.editor_box.model().get_text_with_tags(),
+                if let Err(err) = self
+                    .save
+                    .save_on_exit(&self.save_file_path, || self.notes())
+                {
+                    warn!("Failed to save {}: {}", self.save_file_path.display(), err);
+                    self.status = format!(
+                        "Not quitting, notes.json could not be saved: {}. Ctrl + Shift + Q quits without saving",
+                        err
                     );
+                    return;
                 }
                 relm4::main_application().quit();
             }
+            RootMsg::ForceExitTriggered => {
+                // only when the notes can not be saved, otherwise it would drop what was typed
+                // since the last save
+                if self.save.error.is_some() {
+                    relm4::main_application().quit();
+                }
+            }
+            RootMsg::ExportTriggered(target) => {
+                let blocks = self.editor_box.model().get_blocks();
+                let markdown = logseq::outline(&blocks);
+                let count = blocks
+                    .iter()
+                    .filter(|b| !b.content.text.trim().is_empty())
+                    .count();
+
+                match target {
+                    ExportTarget::Clipboard => {
+                        if let Some(display) = gdk::Display::default() {
+                            display.clipboard().set_text(&markdown);
+                            self.status = format!("Copied {} blocks as a Logseq outline", count);
+                        }
+                    }
+                    ExportTarget::File => export_to_file(markdown, count),
+                }
+            }
+            RootMsg::JournalExportTriggered(index) => {
+                let Some(graph) = self.settings.logseq_graph() else {
+                    self.status = match Settings::path() {
+                        Some(path) => format!("Set logseq_graph in {} first", path.display()),
+                        None => String::from("Set logseq_graph in the settings first"),
+                    };
+                    return;
+                };
+
+                let blocks = self.editor_box.model().get_blocks();
+                let indices = match index {
+                    Some(index) => vec![index],
+                    None => (0..blocks.len()).collect(),
+                }
+                .into_iter()
+                .filter(|&i| {
+                    blocks
+                        .get(i)
+                        .is_some_and(|b| !b.content.text.trim().is_empty())
+                })
+                .collect::<Vec<_>>();
+                if indices.is_empty() {
+                    return;
+                }
+
+                let selected = indices
+                    .iter()
+                    .map(|&i| blocks[i].clone())
+                    .collect::<Vec<_>>();
+                // blocks go on the page of the day they were written
+                let days = logseq::group_by_day(&selected, |block| {
+                    block.created.with_timezone(&chrono::Local).date_naive()
+                });
+
+                let mut written = vec![];
+                let mut paths = vec![];
+                for (date, day) in days {
+                    let day_blocks = day.iter().map(|&i| selected[i].clone()).collect::<Vec<_>>();
+                    match logseq::append_to_journal(&graph, date, &logseq::outline(&day_blocks)) {
+                        Ok(path) => {
+                            written.extend(day.iter().map(|&i| indices[i]));
+                            paths.push(path);
+                        }
+                        Err(err) => {
+                            warn!("Failed to write into the journal of {}: {}", date, err);
+                            self.status = format!("Failed to write into the journal: {}", err);
+                            break;
+                        }
+                    }
+                }
+
+                if written.len() == indices.len() {
+                    self.status = match paths.as_slice() {
+                        [path] => format!("Wrote {} blocks to {}", written.len(), path.display()),
+                        _ => format!(
+                            "Wrote {} blocks to {} journal pages",
+                            written.len(),
+                            paths.len()
+                        ),
+                    };
+                }
+                if self.settings.clear_after_journal_export && !written.is_empty() {
+                    self.editor_box.emit(EditorMsg::RemoveBlocks(written));
+                }
+            }
+            RootMsg::FilterTriggered => {
+                self.filtering = true;
+            }
+            RootMsg::FilterChanged(text) => {
+                self.filtering = text.is_some();
+                let names = text
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect();
+                self.editor_box.emit(EditorMsg::Filter(names));
+            }
+            RootMsg::StatusChanged(status) => {
+                self.status = status;
+            }
             RootMsg::AutoSaveTickTriggered => {
-                if self.text_changed || self.editor_changed {
-                    self.text_changed = false;
-                    self.editor_changed = false;
-                    self.file_writer
-                        .emit(self.editor_box.model().get_text_with_tags());
+                self.editor_box.emit(EditorMsg::RefreshEdited);
+                let changed = self.save.text_changed || self.save.editor_changed;
+                // after a failure the notes are saved on the retry, changed or not
+                let save = match self.save.retry {
+                    Some(retry) => Instant::now() >= retry,
+                    None => changed,
+                };
+                if save && !self.save.saving && !self.save.read_only {
+                    self.save.text_changed = false;
+                    self.save.editor_changed = false;
+                    self.save.saving = true;
+                    self.save.retry = None;
+                    self.file_writer.emit(self.notes());
                 }
             }
         }
     }
 }
 
+/// Whether the notes have changes that are not saved yet and how saving them went.
+#[derive(Default)]
+struct SaveState {
+    text_changed: bool,
+    editor_changed: bool,
+    /// Whether the file writer is saving the notes.
+    saving: bool,
+    /// Why the last save failed, the notes are saved again at `retry`.
+    error: Option<String>,
+    failures: u32,
+    retry: Option<Instant>,
+    /// Whether the notes are not saved at all, when `notes.json` is of a version this build can
+    /// not write without losing what a newer one added.
+    read_only: bool,
+}
+
+impl SaveState {
+    /// Whether quitting now would lose changes, never when the notes are not saved at all.
+    fn unsaved(&self) -> bool {
+        !self.read_only
+            && (self.text_changed || self.editor_changed || self.saving || self.error.is_some())
+    }
+
+    /// Saves the notes before quitting when it would lose changes otherwise.
+    fn save_on_exit(&self, path: &Path, notes: impl FnOnce() -> Snapshot) -> std::io::Result<()> {
+        if self.unsaved() {
+            FileWriter::save(path, notes())?;
+        }
+        Ok(())
+    }
+}
+
+impl App {
+    fn notes(&self) -> Snapshot {
+        Snapshot::new(Notes::new(
+            self.editor_box.model().get_blocks(),
+            self.notes_settings.clone(),
+        ))
+    }
+}
+
+/// Reads the notes, recovering what it can from notes that can not be read and moving them
+/// aside. Notes of an unknown version, or that can not be read or moved aside, are left as they
+/// are and not saved. Gives the blocks, the settings of the pad, what to tell about the notes and
+/// whether they must not be saved.
+fn load_notes(file_path: &Path) -> (Vec<Block>, Map<String, Value>, String, bool) {
+    if !file_path.exists() {
+        return (vec![], Map::new(), String::new(), false);
+    }
+
+    // notes saved before blocks had times were last written when the file was
+    let saved = std::fs::metadata(file_path)
+        .and_then(|metadata| metadata.modified())
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now());
+
+    let bytes = match std::fs::read(file_path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            warn!("Failed to read {}: {}", file_path.display(), err);
+            return (
+                vec![],
+                Map::new(),
+                format!("Failed to read notes.json: {}, changes are not saved", err),
+                true,
+            );
+        }
+    };
+    let notes = match std::str::from_utf8(&bytes).map(Notes::parse) {
+        Ok(Ok(notes)) => Ok(notes),
+        Ok(Err(err @ NotesError::Version(_))) => Err((err.to_string(), true)),
+        Ok(Err(err)) => Err((err.to_string(), false)),
+        Err(err) => Err((err.to_string(), false)),
+    };
+
+    let (mut blocks, settings, status, read_only) = match notes {
+        Ok(notes) => (notes.blocks, notes.settings, String::new(), false),
+        Err((err, true)) => {
+            // likely written by a newer illpad, saving would drop what it added
+            warn!("Not saving {}: {}", file_path.display(), err);
+            let blocks = notes::recover(&String::from_utf8_lossy(&bytes));
+            let status = format!(
+                "notes.json has an {} and is left as it is, changes are not saved",
+                err
+            );
+            (blocks, Map::new(), status, true)
+        }
+        Err((err, false)) => {
+            warn!("Failed to read {}: {}", file_path.display(), err);
+            // the notes of the last save but one lose less than the text of the broken ones
+            let backup = std::fs::read_to_string(backup_path(file_path))
+                .ok()
+                .and_then(|json| Notes::parse(&json).ok());
+            let (blocks, settings, recovered) = match backup {
+                Some(notes) => (notes.blocks, notes.settings, "notes.json.bak"),
+                None => (
+                    notes::recover(&String::from_utf8_lossy(&bytes)),
+                    Map::new(),
+                    "it",
+                ),
+            };
+            // moved aside before the next save could overwrite it
+            match notes::quarantine(file_path) {
+                Ok(corrupt_path) => {
+                    let status = format!(
+                        "notes.json is corrupt ({}), recovered {} blocks from {} and moved it to {}",
+                        err,
+                        blocks.len(),
+                        recovered,
+                        corrupt_path.display()
+                    );
+                    (blocks, settings, status, false)
+                }
+                Err(move_err) => {
+                    warn!("Failed to move {} aside: {}", file_path.display(), move_err);
+                    let status = format!(
+                        "notes.json is corrupt ({}) and could not be moved aside: {}, changes are not saved",
+                        err, move_err
+                    );
+                    (blocks, settings, status, true)
+                }
+            }
+        }
+    };
+
+    for block in &mut blocks {
+        block.date_undated(saved);
+    }
+    (blocks, settings, status, read_only)
+}
+
+/// Asks where to save the outline and writes it there.
+fn export_to_file(markdown: String, count: usize) {
+    let dialog = gtk::FileDialog::builder()
+        .title("Export all blocks")
+        .initial_name("illpad.md")
+        .build();
+    let window = relm4::main_application().active_window();
+
+    dialog.save(window.as_ref(), None::<&gio::Cancellable>, move |result| {
+        let Some(path) = result.ok().and_then(|file| file.path()) else {
+            return;
+        };
+        let status = match std::fs::write(&path, markdown) {
+            Ok(()) => format!("Exported {} blocks to {}", count, path.display()),
+            Err(err) => {
+                warn!("Failed to export to {}: {}", path.display(), err);
+                format!("Failed to export to {}: {}", path.display(), err)
+            }
+        };
+        APP_BROKER.send(RootMsg::StatusChanged(status));
+    });
+}
+
 fn add_key_pressed_event(window: &gtk::ApplicationWindow) {
     let event_controller = gtk::EventControllerKey::new();
 
-    event_controller.connect_key_pressed(|_, key, _, _| {
+    event_controller.connect_key_pressed(|_, key, _, modifier| {
+        let control = modifier.contains(gdk::ModifierType::CONTROL_MASK);
         match key {
             gdk::Key::Escape => {
                 APP_BROKER.send(RootMsg::ExitTriggered);
             }
+            gdk::Key::E if control => {
+                APP_BROKER.send(RootMsg::ExportTriggered(ExportTarget::Clipboard));
+            }
+            gdk::Key::O if control => {
+                APP_BROKER.send(RootMsg::ExportTriggered(ExportTarget::File));
+            }
+            gdk::Key::f if control => {
+                APP_BROKER.send(RootMsg::FilterTriggered);
+            }
+            gdk::Key::Q if control => {
+                APP_BROKER.send(RootMsg::ForceExitTriggered);
+            }
+            gdk::Key::J if control => {
+                APP_BROKER.send(RootMsg::JournalExportTriggered(None));
+            }
             _ => (),
         }
         glib::Propagation::Proceed
@@ -174,3 +550,107 @@ fn add_key_pressed_event(window: &gtk::ApplicationWindow) {
 
     window.add_controller(event_controller);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::BlockMode;
+
+    #[test]
+    fn test_load_notes_of_unknown_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        let json = include_str!("../notes/fixtures/v99.json");
+        std::fs::write(&path, json).unwrap();
+
+        let (_, _, status, read_only) = load_notes(&path);
+        assert!(read_only);
+        assert!(status.contains("unknown version 99"), "{}", status);
+        // neither moved aside nor changed
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), json);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_read_only_never_saves_on_exit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        let json = include_str!("../notes/fixtures/v99.json");
+        std::fs::write(&path, json).unwrap();
+
+        let save = SaveState {
+            text_changed: true,
+            editor_changed: true,
+            saving: true,
+            error: Some(String::from("disk full")),
+            read_only: true,
+            ..Default::default()
+        };
+        assert!(!save.unsaved());
+        save.save_on_exit(&path, || unreachable!()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), json);
+
+        // structural edits alone are unsaved changes too
+        let save = SaveState {
+            editor_changed: true,
+            ..Default::default()
+        };
+        assert!(save.unsaved());
+        save.save_on_exit(&path, || Snapshot::new(Notes::new(vec![], Map::new())))
+            .unwrap();
+        assert_eq!(
+            Notes::parse(&std::fs::read_to_string(&path).unwrap())
+                .unwrap()
+                .version,
+            2
+        );
+    }
+
+    #[test]
+    fn test_load_corrupt_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        std::fs::write(&path, "[{\"text\": \"cut off").unwrap();
+
+        let (blocks, _, status, read_only) = load_notes(&path);
+        assert!(!read_only);
+        assert_eq!(blocks[0].content.text, "cut off");
+        assert!(status.contains("moved it to"), "{}", status);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_load_corrupt_notes_from_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        let backup = include_str!("../notes/fixtures/v2.json");
+        std::fs::write(&path, "{\"version\": 2, \"blocks\": [{\"text\": \"cut").unwrap();
+        std::fs::write(backup_path(&path), backup).unwrap();
+
+        let (blocks, settings, status, read_only) = load_notes(&path);
+        assert!(!read_only);
+        assert_eq!(blocks[0].mode, BlockMode::Math);
+        assert_eq!(settings["theme"], "dark");
+        assert!(status.contains("from notes.json.bak"), "{}", status);
+        // the backup is copied aside too, as the second save replaces it
+        let corrupt = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".corrupt"))
+            .count();
+        assert_eq!(corrupt, 2);
+    }
+
+    #[test]
+    fn test_load_unreadable_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        // reading a directory fails like reading a file without permission
+        let path = dir.path().join("notes.json");
+        std::fs::create_dir(&path).unwrap();
+
+        let (blocks, _, status, read_only) = load_notes(&path);
+        assert!(read_only);
+        assert!(blocks.is_empty());
+        assert!(status.contains("changes are not saved"), "{}", status);
+    }
+}
@@ -1,30 +1,72 @@
-use gtk::{gdk, glib};
+use std::{cell::Cell, rc::Rc};
+
+use chrono::{DateTime, Local, Utc};
+use gtk::{gdk, gio, glib};
+use log::warn;
 use relm4::{gtk, gtk::prelude::*, prelude::*};
 
-use crate::text::TextWithTags;
+use crate::text::{
+    Block, BlockMode, Document, HASHTAG_TAG, HIGHLIGHT_COLOURS, TASK_DONE_TAG, Tag, TaskState,
+    TextWithTags, TokenKind, detect_mode, edited_ago, evaluate, fix_levels, has_hashtags, hashtags,
+    highlight, is_url, sort_siblings, subtree_to_shift, task_edit, task_marker,
+};
 
-use super::{APP_BROKER, RootMsg};
+use super::{APP_BROKER, RootMsg, page_ref::add_page_ref_events};
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Editor {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    /// Kept out of the watched fields, so that typing only redraws `edited`.
+    pub modified: Rc<Cell<DateTime<Utc>>>,
+    /// Hint of when the block was edited.
+    pub edited: gtk::Label,
     pub content: gtk::TextBuffer,
+    pub level: usize,
+    /// Shared with the buffer and key handlers, which draw and copy by the mode.
+    pub mode: Rc<Cell<BlockMode>>,
+    /// Results of the lines of a math block, one per line.
+    pub results: gtk::TextBuffer,
+    /// Whether the block matches the tag filter.
+    pub visible: bool,
 }
 
 #[derive(Debug)]
 pub struct GrabFocus;
 
+/// What `EditorMsg::RequestSortFrom` sorts the siblings of a block by.
+#[derive(Debug, Clone, Copy)]
+pub enum SortBy {
+    /// Oldest first, the order they were written in.
+    Created,
+    /// Last edited first.
+    Modified,
+}
+
 #[derive(Debug)]
 pub enum EditorMsg {
     RequestAddNoteFrom(DynamicIndex),
     RequestDeleteNoteFrom(DynamicIndex),
     ReuestFocusUpFrom(DynamicIndex),
     ReuestFocusDownFrom(DynamicIndex),
-    TextChanged,
+    RequestIndentFrom(DynamicIndex),
+    RequestOutdentFrom(DynamicIndex),
+    RequestJournalExportFrom(DynamicIndex),
+    RequestCycleModeFrom(DynamicIndex),
+    SetModeFrom(DynamicIndex, BlockMode),
+    /// Sorts the block and its siblings, each with its children.
+    RequestSortFrom(DynamicIndex, SortBy),
+    RemoveBlocks(Vec<usize>),
+    /// Shows only the blocks with all of these hashtags, or all blocks when there are none.
+    Filter(Vec<String>),
+    /// Redraws the hints of when the blocks were edited.
+    RefreshEdited,
+    TextChanged(DynamicIndex),
 }
 
 #[relm4::factory(pub)]
 impl FactoryComponent for Editor {
-    type Init = TextWithTags;
+    type Init = Block;
     type Input = GrabFocus;
     type Output = EditorMsg;
     type CommandOutput = ();
@@ -35,6 +77,16 @@ impl FactoryComponent for Editor {
         gtk::Box {
             set_vexpand:false,
             add_css_class: "editor-container",
+            #[watch]
+            set_margin_start: self.level as i32 * 24,
+            #[watch]
+            set_visible: self.visible,
+            #[name (task_check)]
+            gtk::CheckButton {
+                add_css_class: "editor-task-check",
+                set_valign: gtk::Align::Start,
+                set_visible: false,
+            },
             #[name (text_view)]
             gtk::TextView {
                 add_css_class: "editor-text-view",
@@ -43,10 +95,41 @@ impl FactoryComponent for Editor {
                 set_vexpand: true,
                 set_editable: true,
                 set_focusable: true,
-                #[watch]
-                grab_focus: (),
-                set_wrap_mode: gtk::WrapMode::WordChar,
                 set_buffer: Some(&self.content),
+                #[watch]
+                set_monospace: self.mode.get().is_code(),
+                // results are shown line by line, which only lines up without wrapping
+                #[watch]
+                set_wrap_mode: if self.mode.get() == BlockMode::Math {
+                    gtk::WrapMode::None
+                } else {
+                    gtk::WrapMode::WordChar
+                },
+            },
+            gtk::TextView {
+                add_css_class: "editor-text-view",
+                add_css_class: "editor-normal-text-view",
+                add_css_class: "editor-math-results",
+                set_editable: false,
+                set_cursor_visible: false,
+                set_monospace: true,
+                set_justification: gtk::Justification::Right,
+                set_buffer: Some(&self.results),
+                #[watch]
+                set_visible: self.mode.get() == BlockMode::Math,
+            },
+            gtk::Label {
+                add_css_class: "editor-mode-label",
+                set_valign: gtk::Align::Start,
+                #[watch]
+                set_label: self.mode.get().name(),
+                #[watch]
+                set_visible: self.mode.get() != BlockMode::Text,
+            },
+            #[local_ref]
+            edited -> gtk::Label {
+                add_css_class: "editor-edited-label",
+                set_valign: gtk::Align::Start,
             }
         }
     }
@@ -58,13 +141,32 @@ impl FactoryComponent for Editor {
         _: &<Self::ParentWidget as relm4::factory::FactoryView>::ReturnedWidget,
         sender: FactorySender<Self>,
     ) -> Self::Widgets {
+        let edited = &self.edited;
         let widget = view_output!();
+        self.draw_edited();
 
         add_css_class_by_focus(&widget.text_view);
-        add_key_pressed_event(&widget.text_view, index.clone(), sender.clone());
+        add_key_pressed_event(
+            &widget.text_view,
+            index.clone(),
+            self.mode.clone(),
+            sender.clone(),
+        );
+        add_link_click_event(&widget.text_view);
+        add_task_events(&widget.task_check, &self.content);
+        add_page_ref_events(&widget.text_view);
+
+        draw_block(&self.content, &self.results, self.mode.get());
+        self.content.connect_changed({
+            let (results, mode) = (self.results.clone(), self.mode.clone());
+            move |buffer| draw_block(buffer, &results, mode.get())
+        });
 
+        let index = index.clone();
         self.content.connect_changed(move |_| {
-            sender.output(EditorMsg::TextChanged).unwrap();
+            sender
+                .output(EditorMsg::TextChanged(index.clone()))
+                .unwrap();
         });
 
         widget.text_view.grab_focus();
@@ -72,13 +174,47 @@ impl FactoryComponent for Editor {
         widget
     }
 
-    fn init_model(content: Self::Init, _: &DynamicIndex, _: FactorySender<Self>) -> Self {
+    fn init_model(block: Self::Init, _: &DynamicIndex, _: FactorySender<Self>) -> Self {
         Self {
-            content: content.as_text_buffer(),
+            id: block.id,
+            created: block.created,
+            modified: Rc::new(Cell::new(block.modified)),
+            edited: gtk::Label::new(None),
+            content: block.content.as_text_buffer(),
+            level: block.level,
+            mode: Rc::new(Cell::new(block.mode)),
+            results: gtk::TextBuffer::new(None),
+            visible: true,
         }
     }
 
-    fn update(&mut self, _: Self::Input, _sender: FactorySender<Self>) {}
+    fn update_with_view(
+        &mut self,
+        widgets: &mut Self::Widgets,
+        _: Self::Input,
+        sender: FactorySender<Self>,
+    ) {
+        widgets.text_view.grab_focus();
+        self.update_view(widgets, sender);
+    }
+}
+
+impl Editor {
+    /// Marks the block as edited now.
+    fn touch(&self) {
+        self.modified.set(Utc::now());
+        self.draw_edited();
+    }
+
+    fn draw_edited(&self) {
+        let modified = self.modified.get().with_timezone(&Local);
+        self.edited.set_label(&edited_ago(modified, Local::now()));
+        self.edited.set_tooltip_text(Some(&format!(
+            "Created {}\nEdited {}",
+            self.created.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+            modified.format("%Y-%m-%d %H:%M"),
+        )));
+    }
 }
 
 #[derive(Debug)]
@@ -87,17 +223,108 @@ pub struct EditorBox {
 }
 
 impl EditorBox {
-    pub fn get_text_with_tags(&self) -> Vec<TextWithTags> {
+    pub fn get_blocks(&self) -> Vec<Block> {
+        let mut levels = self.editors.iter().map(|e| e.level).collect::<Vec<_>>();
+        fix_levels(&mut levels);
+
         self.editors
             .iter()
-            .map(|e| TextWithTags::from(&e.content, e.content.start_iter(), e.content.end_iter()))
+            .zip(levels)
+            .map(|(e, level)| {
+                let mut content =
+                    TextWithTags::from(&e.content, e.content.start_iter(), e.content.end_iter());
+                content.normalize();
+                Block {
+                    id: e.id.clone(),
+                    created: e.created,
+                    modified: e.modified.get(),
+                    level,
+                    mode: e.mode.get(),
+                    content,
+                }
+            })
             .collect()
     }
+
+    /// Indents or outdents the block at `index` together with its children.
+    fn shift(&mut self, index: usize, outdent: bool) {
+        let levels = self.editors.iter().map(|e| e.level).collect::<Vec<_>>();
+        let Some(range) = subtree_to_shift(&levels, index, outdent) else {
+            return;
+        };
+
+        let mut editors = self.editors.guard();
+        for index in range {
+            let editor = editors.get_mut(index).unwrap();
+            if outdent {
+                editor.level -= 1;
+            } else {
+                editor.level += 1;
+            }
+        }
+        APP_BROKER.send(RootMsg::EditorChanged);
+    }
+
+    fn sort(&mut self, index: usize, by: SortBy) {
+        let mut levels = self.editors.iter().map(|e| e.level).collect::<Vec<_>>();
+        fix_levels(&mut levels);
+        let times = self
+            .editors
+            .iter()
+            .map(|e| (e.created, e.modified.get()))
+            .collect::<Vec<_>>();
+        let order = match by {
+            SortBy::Created => sort_siblings(&levels, index, |i| times[i].0),
+            SortBy::Modified => sort_siblings(&levels, index, |i| std::cmp::Reverse(times[i].1)),
+        };
+
+        // moves the editors into the order one by one, `positions` holds where each one was
+        let mut editors = self.editors.guard();
+        let mut positions = (0..editors.len()).collect::<Vec<_>>();
+        for (target, index) in order.iter().enumerate() {
+            let current = positions.iter().position(|i| i == index).unwrap();
+            if current != target {
+                editors.move_to(current, target);
+                let moved = positions.remove(current);
+                positions.insert(target, moved);
+            }
+            if editors
+                .get(target)
+                .is_some_and(|e| e.level != levels[*index])
+            {
+                editors.get_mut(target).unwrap().level = levels[*index];
+            }
+        }
+        if let Some(target) = order.iter().position(|i| *i == index) {
+            editors.send(target, GrabFocus {});
+        }
+
+        APP_BROKER.send(RootMsg::StatusChanged(String::from(match by {
+            SortBy::Created => "Sorted the blocks by when they were written",
+            SortBy::Modified => "Sorted the blocks by when they were last edited",
+        })));
+        APP_BROKER.send(RootMsg::EditorChanged);
+    }
+
+    fn set_mode(&mut self, index: usize, mode: BlockMode) {
+        let mut editors = self.editors.guard();
+        let Some(editor) = editors.get_mut(index) else {
+            return;
+        };
+        editor.mode.set(mode);
+        draw_block(&editor.content, &editor.results, mode);
+
+        APP_BROKER.send(RootMsg::StatusChanged(format!(
+            "Block mode: {} (Ctrl + M to change, Ctrl + Shift + M for text)",
+            mode.name()
+        )));
+        APP_BROKER.send(RootMsg::EditorChanged);
+    }
 }
 
 #[relm4::component(pub)]
 impl SimpleComponent for EditorBox {
-    type Init = Vec<TextWithTags>;
+    type Init = Vec<Block>;
     type Input = EditorMsg;
     type Output = ();
 
@@ -111,7 +338,7 @@ impl SimpleComponent for EditorBox {
     }
 
     fn init(
-        mut text_with_tags: Self::Init,
+        mut blocks: Self::Init,
         root: Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
@@ -119,17 +346,38 @@ impl SimpleComponent for EditorBox {
             .launch_default()
             .forward(sender.input_sender(), std::convert::identity);
 
-        if text_with_tags.is_empty() {
+        if blocks.is_empty() {
             let text = r#"Welcome to the illpad!
-Ctrl + Enter           Add new block below the current block"#;
-
-            text_with_tags.push(TextWithTags::from_str(text));
+Ctrl + Enter           Add new block below the current block
+Tab / Shift + Tab      Indent or outdent the block with its children, at the start of code
+Ctrl + B / I / U       Bold, italic and underline the selection
+Ctrl + H               Highlight the selection
+Ctrl + 1 … 5           Highlight in yellow, red, green, blue or purple
+Ctrl + E               Mark the selection as inline code
+Ctrl + Shift + S       Strike the selection through
+Ctrl + T               Cycle the block through TODO, DOING and DONE
+Ctrl + M               Cycle the block through text, Markdown, code and math modes
+Ctrl + Shift + M       Turn the block back into text, e.g. after pasting code into it
+Ctrl + Shift + D       Sort the block and its siblings by when they were written
+Ctrl + Shift + R       Sort the block and its siblings by when they were last edited
+Ctrl + V               Paste a URL over the selection to make it a link
+Ctrl + click           Open a link
+[[                     Complete a page name of the Logseq graph
+Ctrl + F               Show only the blocks with some #tags
+Ctrl + Shift + E       Copy all blocks as a Logseq outline
+Ctrl + Shift + O       Export all blocks as a Logseq outline to a file
+Ctrl + Shift + L       Write the block into the Logseq journal of the day it was written
+Ctrl + Shift + J       Write all blocks into the Logseq journals of their days
+Escape                 Save and quit
+Ctrl + Shift + Q       Quit without saving, when the notes can not be saved"#;
+
+            blocks.push(TextWithTags::from_str(text).into());
         }
 
         let mut model = EditorBox { editors };
 
-        for text_with_tags in text_with_tags {
-            model.editors.guard().push_back(text_with_tags);
+        for block in blocks {
+            model.editors.guard().push_back(block);
         }
 
         let editor_box = model.editors.widget();
@@ -141,13 +389,25 @@ Ctrl + Enter           Add new block below the current block"#;
 
     fn update(&mut self, msg: Self::Input, _sender: ComponentSender<Self>) {
         match msg {
-            EditorMsg::TextChanged => {
+            EditorMsg::TextChanged(index) => {
+                // borrowing the editor mutably would redraw all of it on every key
+                if let Some(editor) = self.editors.get(index.current_index()) {
+                    editor.touch();
+                }
                 APP_BROKER.send(RootMsg::TextChanged);
             }
+            EditorMsg::RefreshEdited => {
+                for editor in self.editors.iter() {
+                    editor.draw_edited();
+                }
+            }
             EditorMsg::RequestAddNoteFrom(index) => {
-                self.editors
-                    .guard()
-                    .insert(index.current_index() + 1, TextWithTags::default());
+                let index = index.current_index();
+                let block = Block {
+                    level: self.editors.get(index).map_or(0, |e| e.level),
+                    ..Default::default()
+                };
+                self.editors.guard().insert(index + 1, block);
                 APP_BROKER.send(RootMsg::EditorChanged);
             }
             EditorMsg::RequestDeleteNoteFrom(index) => {
@@ -164,6 +424,50 @@ Ctrl + Enter           Add new block below the current block"#;
                 }
                 APP_BROKER.send(RootMsg::EditorChanged);
             }
+            EditorMsg::Filter(names) => {
+                let mut editors = self.editors.guard();
+                for index in 0..editors.len() {
+                    let editor = editors.get_mut(index).unwrap();
+                    let (start, end) = editor.content.bounds();
+                    editor.visible = has_hashtags(
+                        &editor.content.text(&start, &end, false),
+                        editor.mode.get(),
+                        &names,
+                    );
+                }
+            }
+            EditorMsg::RequestIndentFrom(index) => {
+                self.shift(index.current_index(), false);
+            }
+            EditorMsg::RequestOutdentFrom(index) => {
+                self.shift(index.current_index(), true);
+            }
+            EditorMsg::RequestJournalExportFrom(index) => {
+                APP_BROKER.send(RootMsg::JournalExportTriggered(Some(index.current_index())));
+            }
+            EditorMsg::RequestCycleModeFrom(index) => {
+                let index = index.current_index();
+                if let Some(editor) = self.editors.get(index) {
+                    self.set_mode(index, editor.mode.get().cycle());
+                }
+            }
+            EditorMsg::SetModeFrom(index, mode) => {
+                self.set_mode(index.current_index(), mode);
+            }
+            EditorMsg::RequestSortFrom(index, by) => {
+                self.sort(index.current_index(), by);
+            }
+            EditorMsg::RemoveBlocks(mut indices) => {
+                indices.sort();
+                let mut editors = self.editors.guard();
+                for index in indices.into_iter().rev() {
+                    editors.remove(index);
+                }
+                if editors.is_empty() {
+                    editors.push_back(Block::default());
+                }
+                APP_BROKER.send(RootMsg::EditorChanged);
+            }
             EditorMsg::ReuestFocusUpFrom(index) => {
                 if index.current_index() == 0 {
                     return;
@@ -189,11 +493,21 @@ Ctrl + Enter           Add new block below the current block"#;
 pub fn add_key_pressed_event(
     text_view: &gtk::TextView,
     index: DynamicIndex,
+    mode: Rc<Cell<BlockMode>>,
     sender: FactorySender<Editor>,
 ) {
     let event_controller = gtk::EventControllerKey::new();
 
     let text_view_clone = text_view.clone();
+    // applying a tag leaves the text as it is, so the buffer does not tell it changed
+    let tags_changed = {
+        let (sender, index) = (sender.clone(), index.clone());
+        move || {
+            sender
+                .output(EditorMsg::TextChanged(index.clone()))
+                .unwrap();
+        }
+    };
 
     event_controller.connect_key_pressed(move |_, key, _, modifier| {
         return match key {
@@ -218,39 +532,173 @@ pub fn add_key_pressed_event(
                     glib::Propagation::Proceed
                 }
             }
+            gdk::Key::Tab | gdk::Key::ISO_Left_Tab => {
+                // code is indented with tabs, except at the start of the block
+                let buffer = text_view_clone.buffer();
+                let at_start = buffer.cursor_position() == 0 && !buffer.has_selection();
+                if (mode.get().is_code() && !at_start)
+                    || modifier
+                        .intersects(gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::ALT_MASK)
+                {
+                    return glib::Propagation::Proceed;
+                }
+
+                let msg = if key == gdk::Key::Tab {
+                    EditorMsg::RequestIndentFrom(index.clone())
+                } else {
+                    EditorMsg::RequestOutdentFrom(index.clone())
+                };
+                sender.output(msg).unwrap();
+                glib::Propagation::Stop
+            }
             gdk::Key::c => {
                 if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
-                    if let Some((start, end)) = text_view_clone.buffer().selection_bounds() {
-                        let clipboard_text =
-                            TextWithTags::from(&text_view_clone.buffer(), start, end)
-                                .clipboard_text();
-
-                        text_view_clone.clipboard().set_text(&clipboard_text);
+                    let buffer = text_view_clone.buffer();
+                    if mode.get() != BlockMode::Text
+                        && let Some((start, end)) = buffer.selection_bounds()
+                    {
+                        // source is copied as it is, without Markdown markers
+                        let text = buffer.text(&start, &end, false);
+                        text_view_clone.clipboard().set_text(&text);
+                        return glib::Propagation::Stop;
+                    }
+                    if let Some((start, end)) = buffer.selection_bounds() {
+                        let selection: TextWithTags =
+                            Document::from_text_buffer_range(&buffer, &start, &end).into();
+                        let html = selection.html_text();
+
+                        // the receiving app picks the richest flavour it understands
+                        let content = gdk::ContentProvider::new_union(&[
+                            gdk::ContentProvider::for_bytes(
+                                "text/html",
+                                &glib::Bytes::from_owned(html.into_bytes()),
+                            ),
+                            gdk::ContentProvider::for_value(&selection.clipboard_text().to_value()),
+                        ]);
+                        if let Err(err) = text_view_clone.clipboard().set_content(Some(&content)) {
+                            warn!("Failed to copy the selection: {}", err);
+                        }
                         return glib::Propagation::Stop;
                     }
                 }
                 glib::Propagation::Proceed
             }
+            gdk::Key::v => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    let (sender, index) = (sender.clone(), index.clone());
+                    paste(
+                        &text_view_clone,
+                        mode.get(),
+                        move |mode| {
+                            sender
+                                .output(EditorMsg::SetModeFrom(index.clone(), mode))
+                                .unwrap();
+                        },
+                        tags_changed.clone(),
+                    );
+                    return glib::Propagation::Stop;
+                }
+                glib::Propagation::Proceed
+            }
             gdk::Key::h => {
                 if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
-                    if let Some((start, end)) = text_view_clone.buffer().selection_bounds() {
-                        text_view_clone
-                            .buffer()
-                            .apply_tag_by_name("highlight", &start, &end);
-                    }
+                    apply_tag_to_selection(&text_view_clone, "highlight", &tags_changed);
+                }
+                glib::Propagation::Proceed
+            }
+            gdk::Key::_1 | gdk::Key::_2 | gdk::Key::_3 | gdk::Key::_4 | gdk::Key::_5 => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK)
+                    && let Some(digit) = key.to_unicode().and_then(|c| c.to_digit(10))
+                {
+                    let colour = &HIGHLIGHT_COLOURS[digit as usize - 1];
+                    apply_tag_to_selection(&text_view_clone, colour.name, &tags_changed);
                 }
                 glib::Propagation::Proceed
             }
             gdk::Key::b => {
                 if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
-                    if let Some((start, end)) = text_view_clone.buffer().selection_bounds() {
-                        text_view_clone
-                            .buffer()
-                            .apply_tag_by_name("bold", &start, &end);
-                    }
+                    apply_tag_to_selection(&text_view_clone, "bold", &tags_changed);
+                }
+                glib::Propagation::Proceed
+            }
+            gdk::Key::i => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    apply_tag_to_selection(&text_view_clone, "italic", &tags_changed);
+                }
+                glib::Propagation::Proceed
+            }
+            gdk::Key::u => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    apply_tag_to_selection(&text_view_clone, "underline", &tags_changed);
+                }
+                glib::Propagation::Proceed
+            }
+            gdk::Key::e => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    apply_tag_to_selection(&text_view_clone, "code", &tags_changed);
+                }
+                glib::Propagation::Proceed
+            }
+            gdk::Key::S => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    apply_tag_to_selection(&text_view_clone, "strikethrough", &tags_changed);
                 }
                 glib::Propagation::Proceed
             }
+            gdk::Key::L => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    sender
+                        .output(EditorMsg::RequestJournalExportFrom(index.clone()))
+                        .unwrap();
+                    glib::Propagation::Stop
+                } else {
+                    glib::Propagation::Proceed
+                }
+            }
+            gdk::Key::m => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    sender
+                        .output(EditorMsg::RequestCycleModeFrom(index.clone()))
+                        .unwrap();
+                    glib::Propagation::Stop
+                } else {
+                    glib::Propagation::Proceed
+                }
+            }
+            gdk::Key::M => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    sender
+                        .output(EditorMsg::SetModeFrom(index.clone(), BlockMode::Text))
+                        .unwrap();
+                    glib::Propagation::Stop
+                } else {
+                    glib::Propagation::Proceed
+                }
+            }
+            gdk::Key::D | gdk::Key::R => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    let by = if key == gdk::Key::D {
+                        SortBy::Created
+                    } else {
+                        SortBy::Modified
+                    };
+                    sender
+                        .output(EditorMsg::RequestSortFrom(index.clone(), by))
+                        .unwrap();
+                    glib::Propagation::Stop
+                } else {
+                    glib::Propagation::Proceed
+                }
+            }
+            gdk::Key::t => {
+                if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
+                    let buffer = text_view_clone.buffer();
+                    set_task_state(&buffer, TaskState::cycle(task_state(&buffer)));
+                    glib::Propagation::Stop
+                } else {
+                    glib::Propagation::Proceed
+                }
+            }
             gdk::Key::j => {
                 if modifier.contains(gdk::ModifierType::CONTROL_MASK) {
                     sender
@@ -278,6 +726,178 @@ pub fn add_key_pressed_event(
     text_view.add_controller(event_controller);
 }
 
+/// Applies a tag over the selection and calls `on_change` when there is one.
+fn apply_tag_to_selection(text_view: &gtk::TextView, name: &str, on_change: &impl Fn()) {
+    let buffer = text_view.buffer();
+    if let Some((start, end)) = buffer.selection_bounds() {
+        let mut document = Document::from_text_buffer_range(&buffer, &start, &end);
+        document.apply_tag(Tag {
+            start: 0,
+            end: document.char_count(),
+            name: name.to_string(),
+            ..Default::default()
+        });
+        document.write_tags_to_range(&buffer, start.offset());
+        on_change();
+    }
+}
+
+/// Pastes the clipboard with its formatting, read from HTML when the clipboard has it (e.g.
+/// copied from a browser) and from Markdown otherwise. A URL pasted over a selection turns the
+/// selection into a link instead of replacing it. Blocks in other modes than text get the plain
+/// text. Pasting into an empty text block, e.g. one just added with Ctrl + Enter, guesses the
+/// mode of the block from the pasted text and passes it to `on_detect`. `on_link` is called
+/// after a link was made, which changes no text.
+fn paste(
+    text_view: &gtk::TextView,
+    mode: BlockMode,
+    on_detect: impl Fn(BlockMode) + 'static,
+    on_link: impl Fn() + 'static,
+) {
+    let text_view = text_view.clone();
+    let clipboard = text_view.clipboard();
+
+    glib::spawn_future_local(async move {
+        let text = clipboard.read_text_future().await.ok().flatten();
+        let buffer = text_view.buffer();
+
+        let mode = match text.as_deref() {
+            Some(text) if mode == BlockMode::Text && buffer.char_count() == 0 => {
+                let detected = detect_mode(text);
+                if detected != BlockMode::Text {
+                    on_detect(detected);
+                }
+                detected
+            }
+            _ => mode,
+        };
+        if mode != BlockMode::Text {
+            if let Some(text) = text {
+                buffer.begin_user_action();
+                buffer.delete_selection(true, text_view.is_editable());
+                buffer.insert_at_cursor(&text);
+                buffer.end_user_action();
+                text_view.scroll_mark_onscreen(&buffer.get_insert());
+            }
+            return;
+        }
+
+        let html = if clipboard.formats().contain_mime_type("text/html") {
+            read_html(&clipboard)
+                .await
+                .inspect_err(|err| warn!("Failed to read HTML from the clipboard: {}", err))
+                .ok()
+        } else {
+            None
+        };
+
+        if let Some((start, end)) = buffer.selection_bounds()
+            && let Some(url) = text.as_deref().filter(|text| is_url(text))
+        {
+            let mut document = Document::from_text_buffer_range(&buffer, &start, &end);
+            document.apply_tag(Tag::link(0, document.char_count(), url.trim()));
+            document.write_tags_to_range(&buffer, start.offset());
+            on_link();
+            return;
+        }
+
+        let pasted = match (html, text) {
+            (Some(html), _) => TextWithTags::from_html(&html),
+            (None, Some(markdown)) => TextWithTags::from_markdown(&markdown),
+            (None, None) => return,
+        };
+        // only the selection is read and written back, the rest of the block is left as it is
+        let (start, end) = buffer.selection_bounds().unwrap_or_else(|| {
+            let cursor = buffer.iter_at_mark(&buffer.get_insert());
+            (cursor, cursor)
+        });
+        let offset = start.offset();
+        let mut document = Document::from_text_buffer_range(&buffer, &start, &end);
+        document.delete(0, document.char_count());
+        document.insert_with_tags(0, &pasted);
+
+        buffer.begin_user_action();
+        buffer.delete_selection(true, text_view.is_editable());
+        let mut iter = buffer.iter_at_mark(&buffer.get_insert());
+        buffer.insert(&mut iter, &pasted.text);
+        document.write_tags_to_range(&buffer, offset);
+        buffer.end_user_action();
+
+        text_view.scroll_mark_onscreen(&buffer.get_insert());
+    });
+}
+
+async fn read_html(clipboard: &gdk::Clipboard) -> Result<String, glib::Error> {
+    let (input, _) = clipboard
+        .read_future(&["text/html"], glib::Priority::DEFAULT)
+        .await?;
+    let output = gio::MemoryOutputStream::new_resizable();
+    output
+        .splice_future(
+            &input,
+            gio::OutputStreamSpliceFlags::CLOSE_SOURCE | gio::OutputStreamSpliceFlags::CLOSE_TARGET,
+            glib::Priority::DEFAULT,
+        )
+        .await?;
+    let bytes = output.steal_as_bytes();
+
+    // Firefox offers its HTML as UTF-16
+    Ok(match bytes.strip_prefix(&[0xFF, 0xFE]) {
+        Some(utf16) => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        None => String::from_utf8_lossy(&bytes).into_owned(),
+    })
+}
+
+/// Opens the link under the pointer on Ctrl + click.
+fn add_link_click_event(text_view: &gtk::TextView) {
+    let gesture = gtk::GestureClick::new();
+    gesture.set_button(gdk::BUTTON_PRIMARY);
+
+    let text_view_clone = text_view.clone();
+    gesture.connect_pressed(move |gesture, _, x, y| {
+        if !gesture
+            .current_event_state()
+            .contains(gdk::ModifierType::CONTROL_MASK)
+        {
+            return;
+        }
+
+        let (x, y) = text_view_clone.window_to_buffer_coords(
+            gtk::TextWindowType::Widget,
+            x as i32,
+            y as i32,
+        );
+        let Some(iter) = text_view_clone.iter_at_location(x, y) else {
+            return;
+        };
+
+        let href = iter.tags().iter().find_map(|tag| {
+            tag.name()
+                .and_then(|name| name.strip_prefix("link:").map(|href| href.to_string()))
+        });
+
+        if let Some(href) = href {
+            gesture.set_state(gtk::EventSequenceState::Claimed);
+            gtk::UriLauncher::new(&href).launch(
+                text_view_clone.root().and_downcast_ref::<gtk::Window>(),
+                None::<&gio::Cancellable>,
+                move |result| {
+                    if let Err(error) = result {
+                        warn!("failed to open {}: {}", href, error);
+                    }
+                },
+            );
+        }
+    });
+
+    text_view.add_controller(gesture);
+}
+
 pub fn add_css_class_by_focus(text_view: &gtk::TextView) {
     text_view.connect_has_focus_notify(|text_view| {
         if text_view.has_focus() {
@@ -289,3 +909,107 @@ pub fn add_css_class_by_focus(text_view: &gtk::TextView) {
         }
     });
 }
+
+fn draw_hashtags(buffer: &gtk::TextBuffer) {
+    let (start, end) = buffer.bounds();
+    buffer.remove_tag_by_name(HASHTAG_TAG, &start, &end);
+
+    for tag in hashtags(&buffer.text(&start, &end, false)) {
+        buffer.apply_tag_by_name(
+            HASHTAG_TAG,
+            &buffer.iter_at_offset(tag.start),
+            &buffer.iter_at_offset(tag.end),
+        );
+    }
+}
+
+/// Redraws what is drawn over the text of a block by its mode: hashtags in text, syntax
+/// highlighting in the other modes and the results of math blocks.
+fn draw_block(buffer: &gtk::TextBuffer, results: &gtk::TextBuffer, mode: BlockMode) {
+    let (start, end) = buffer.bounds();
+    if mode.is_code() {
+        buffer.remove_tag_by_name(HASHTAG_TAG, &start, &end);
+    } else {
+        draw_hashtags(buffer);
+    }
+    draw_syntax(buffer, mode);
+
+    if mode == BlockMode::Math {
+        let lines = evaluate(&buffer.text(&start, &end, false))
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect::<Vec<_>>();
+        results.set_text(&lines.join("\n"));
+    } else {
+        results.set_text("");
+    }
+}
+
+/// Redraws the syntax highlighting of a block, removing it from text blocks.
+fn draw_syntax(buffer: &gtk::TextBuffer, mode: BlockMode) {
+    let (start, end) = buffer.bounds();
+    for kind in TokenKind::ALL {
+        buffer.remove_tag_by_name(kind.tag_name(), &start, &end);
+    }
+
+    for token in highlight(mode, &buffer.text(&start, &end, false)) {
+        buffer.apply_tag_by_name(
+            token.kind.tag_name(),
+            &buffer.iter_at_offset(token.start),
+            &buffer.iter_at_offset(token.end),
+        );
+    }
+}
+
+fn task_state(buffer: &gtk::TextBuffer) -> Option<TaskState> {
+    let prefix = buffer.text(&buffer.start_iter(), &buffer.iter_at_offset(6), false);
+    task_marker(&prefix).map(|(state, _)| state)
+}
+
+/// Replaces the task marker at the start of a block.
+fn set_task_state(buffer: &gtk::TextBuffer, state: Option<TaskState>) {
+    let prefix = buffer.text(&buffer.start_iter(), &buffer.iter_at_offset(6), false);
+    let (len, marker) = task_edit(&prefix, state);
+
+    buffer.begin_user_action();
+    let (mut start, mut end) = (buffer.start_iter(), buffer.iter_at_offset(len));
+    buffer.delete(&mut start, &mut end);
+    buffer.insert(&mut start, &marker);
+    buffer.end_user_action();
+}
+
+/// Shows the checkbox of task blocks and strikes done ones through. Checking the box marks the
+/// task as done, unchecking it as to do.
+fn add_task_events(check: &gtk::CheckButton, buffer: &gtk::TextBuffer) {
+    let update = {
+        let check = check.clone();
+        move |buffer: &gtk::TextBuffer| {
+            let state = task_state(buffer);
+            let done = state.is_some_and(|state| state.is_done());
+            check.set_visible(state.is_some());
+            check.set_active(done);
+
+            let (start, end) = buffer.bounds();
+            if done {
+                buffer.apply_tag_by_name(TASK_DONE_TAG, &start, &end);
+            } else {
+                buffer.remove_tag_by_name(TASK_DONE_TAG, &start, &end);
+            }
+        }
+    };
+    update(buffer);
+    buffer.connect_changed(update);
+
+    let buffer = buffer.clone();
+    check.connect_toggled(move |check| {
+        let done = task_state(&buffer).is_some_and(|state| state.is_done());
+        if check.is_active() != done {
+            let state = if check.is_active() {
+                TaskState::Done
+            } else {
+                TaskState::Todo
+            };
+            set_task_state(&buffer, Some(state));
+        }
+    });
+}
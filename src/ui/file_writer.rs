@@ -1,9 +1,39 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
-use log::trace;
+use log::{trace, warn};
 use relm4::{ComponentSender, Worker};
 
-use crate::text::TextWithTags;
+use crate::{fs::write_with_backup, notes::Notes};
+
+/// Generation of the last snapshot written, held while saving. A snapshot still queued in the
+/// worker when the save on quit ran is older and skipped, so it can not overwrite newer notes.
+static SAVE_LOCK: Mutex<u64> = Mutex::new(0);
+
+/// Generation of the last snapshot taken.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Notes to save, numbered in the order they were taken.
+#[derive(Debug)]
+pub struct Snapshot {
+    generation: u64,
+    notes: Notes,
+}
+
+impl Snapshot {
+    pub fn new(notes: Notes) -> Self {
+        Self {
+            generation: GENERATION.fetch_add(1, Ordering::SeqCst) + 1,
+            notes,
+        }
+    }
+}
 
 pub struct FileWriter {
     path: PathBuf,
@@ -12,36 +42,41 @@ pub struct FileWriter {
 #[derive(Debug)]
 pub enum FileWriterMsg {
     SaveComplete,
+    SaveFailed(String),
 }
 
-impl FileWriter {
-    pub fn save(path: &Path, input: Vec<TextWithTags>) {
-        trace!("FileWriter::save start");
-
-        let dir = dirs::home_dir().unwrap().join(".cache/illpad");
-        if !dir.exists() {
-            std::fs::create_dir_all(&dir).unwrap();
-        }
+/// How long to wait before saving again after `failures` saves in a row failed.
+pub fn retry_delay(failures: u32) -> Duration {
+    let seconds = 10u64.saturating_mul(1 << failures.saturating_sub(1).min(10));
+    Duration::from_secs(seconds.min(300))
+}
 
-        if !path.exists() {
-            if let Some(parent) = path.parent() {
-                if !parent.exists() {
-                    std::fs::create_dir_all(parent).unwrap();
-                }
-            }
+impl FileWriter {
+    /// Saves the notes without ever leaving `path` half written, keeping the notes it held at
+    /// `notes.json.bak`. Does nothing when newer notes were saved already.
+    pub fn save(path: &Path, input: Snapshot) -> std::io::Result<()> {
+        let mut written = SAVE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        if input.generation < *written {
+            trace!("FileWriter::save skipped, newer notes were saved");
+            return Ok(());
         }
+        trace!("FileWriter::save start");
 
-        if let Ok(json) = serde_json::to_string_pretty(&input) {
-            std::fs::write(path, json).unwrap();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
         }
+        let json = input.notes.to_json()?;
+        write_with_backup(path, json.as_bytes())?;
+        *written = input.generation;
 
         trace!("FileWriter::save finish");
+        Ok(())
     }
 }
 
 impl Worker for FileWriter {
     type Init = PathBuf;
-    type Input = Vec<TextWithTags>;
+    type Input = Snapshot;
     type Output = FileWriterMsg;
 
     fn init(path: Self::Init, _sender: ComponentSender<Self>) -> Self {
@@ -49,7 +84,50 @@ impl Worker for FileWriter {
     }
 
     fn update(&mut self, input: Self::Input, sender: ComponentSender<Self>) {
-        Self::save(self.path.as_path(), input);
-        sender.output(FileWriterMsg::SaveComplete).unwrap();
+        let msg = match Self::save(self.path.as_path(), input) {
+            Ok(()) => FileWriterMsg::SaveComplete,
+            Err(err) => {
+                warn!("Failed to save {}: {}", self.path.display(), err);
+                FileWriterMsg::SaveFailed(err.to_string())
+            }
+        };
+        // the app is gone when it quit while saving
+        let _ = sender.output(msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::TextWithTags;
+
+    #[test]
+    fn test_retry_delay() {
+        let delays = (1..=7)
+            .map(|failures| retry_delay(failures).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [10, 20, 40, 80, 160, 300, 300]);
+        assert_eq!(retry_delay(u32::MAX).as_secs(), 300);
+    }
+
+    #[test]
+    fn test_save_skips_older_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        let snapshot = |text: &str| {
+            Snapshot::new(Notes::new(
+                vec![TextWithTags::from_str(text).into()],
+                Default::default(),
+            ))
+        };
+
+        // the worker's snapshot was taken first but is written after the one on quit
+        let queued = snapshot("queued");
+        FileWriter::save(&path, snapshot("on quit")).unwrap();
+        FileWriter::save(&path, queued).unwrap();
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        let notes = Notes::parse(&json).unwrap();
+        assert_eq!(notes.blocks[0].content.text, "on quit");
     }
 }
@@ -4,14 +4,33 @@ use relm4::{MessageBroker, RelmApp};
 pub mod app;
 pub mod editor_box;
 pub mod file_writer;
+pub mod page_ref;
 
 #[derive(Debug)]
 pub enum RootMsg {
     TextChanged,
     EditorChanged,
     SaveComplete,
+    SaveFailed(String),
     AutoSaveTickTriggered,
     ExitTriggered,
+    /// Quits even when the notes can not be saved.
+    ForceExitTriggered,
+    ExportTriggered(ExportTarget),
+    /// Writes the block at the index, or all blocks, into the Logseq journal pages of the days
+    /// they were written.
+    JournalExportTriggered(Option<usize>),
+    StatusChanged(String),
+    FilterTriggered,
+    /// The text of the tag filter bar changed, `None` when the bar was closed.
+    FilterChanged(Option<String>),
+}
+
+/// Where "export all blocks" writes the Logseq outline to.
+#[derive(Debug)]
+pub enum ExportTarget {
+    Clipboard,
+    File,
 }
 
 pub(crate) static APP_BROKER: MessageBroker<RootMsg> = MessageBroker::new();
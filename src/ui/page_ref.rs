@@ -0,0 +1,201 @@
+use std::{cell::RefCell, rc::Rc};
+
+use gtk::{gdk, glib};
+use log::warn;
+use relm4::{gtk, gtk::prelude::*};
+
+use crate::{
+    logseq::{complete, page_names},
+    settings::Settings,
+    text::{PAGE_REF_TAG, page_ref_query, page_refs},
+};
+
+/// Number of page names shown while completing.
+const MAX_COMPLETIONS: usize = 10;
+
+#[derive(Default)]
+struct Completion {
+    /// Page names of the graph, read when a `[[` is typed.
+    pages: Option<Vec<String>>,
+    /// Page names shown in the popover.
+    shown: Vec<String>,
+}
+
+/// Draws `[[page]]` references and completes page names of the Logseq graph set in the
+/// settings when `[[` is typed.
+pub fn add_page_ref_events(text_view: &gtk::TextView) {
+    let list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::Browse)
+        .build();
+    let popover = gtk::Popover::builder()
+        .child(&list)
+        .autohide(false)
+        .has_arrow(false)
+        .can_focus(false)
+        .position(gtk::PositionType::Bottom)
+        .build();
+    popover.set_parent(text_view);
+
+    let completion = Rc::new(RefCell::new(Completion::default()));
+
+    let buffer = text_view.buffer();
+    draw_page_refs(&buffer);
+    buffer.connect_changed({
+        let (text_view, popover, list, completion) = (
+            text_view.clone(),
+            popover.clone(),
+            list.clone(),
+            completion.clone(),
+        );
+        move |buffer| {
+            draw_page_refs(buffer);
+            update_completion(&text_view, &popover, &list, &mut completion.borrow_mut());
+        }
+    });
+
+    list.connect_row_activated({
+        let (text_view, popover, completion) =
+            (text_view.clone(), popover.clone(), completion.clone());
+        move |_, row| {
+            let name = completion.borrow().shown.get(row.index() as usize).cloned();
+            if let Some(name) = name {
+                popover.popdown();
+                accept(&text_view, &name);
+            }
+        }
+    });
+
+    let event_controller = gtk::EventControllerKey::new();
+    // before the keys reach the text view or the other handlers
+    event_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    event_controller.connect_key_pressed({
+        let (popover, list) = (popover.clone(), list.clone());
+        move |_, key, _, _| {
+            if !popover.is_visible() {
+                return glib::Propagation::Proceed;
+            }
+
+            let selected = list.selected_row().map_or(0, |row| row.index());
+            match key {
+                gdk::Key::Down => {
+                    list.select_row(list.row_at_index(selected + 1).as_ref());
+                }
+                gdk::Key::Up => {
+                    list.select_row(list.row_at_index((selected - 1).max(0)).as_ref());
+                }
+                gdk::Key::Return | gdk::Key::KP_Enter | gdk::Key::Tab => {
+                    if let Some(row) = list.selected_row() {
+                        row.activate();
+                    }
+                }
+                gdk::Key::Escape => popover.popdown(),
+                _ => return glib::Propagation::Proceed,
+            }
+            glib::Propagation::Stop
+        }
+    });
+    text_view.add_controller(event_controller);
+
+    text_view.connect_has_focus_notify({
+        let popover = popover.clone();
+        move |text_view| {
+            if !text_view.has_focus() {
+                popover.popdown();
+            }
+        }
+    });
+    text_view.connect_destroy(move |_| popover.unparent());
+}
+
+fn draw_page_refs(buffer: &gtk::TextBuffer) {
+    let (start, end) = buffer.bounds();
+    buffer.remove_tag_by_name(PAGE_REF_TAG, &start, &end);
+
+    for (start, end) in page_refs(&buffer.text(&start, &end, false)) {
+        buffer.apply_tag_by_name(
+            PAGE_REF_TAG,
+            &buffer.iter_at_offset(start),
+            &buffer.iter_at_offset(end),
+        );
+    }
+}
+
+/// The `[[` being typed before the cursor, as its offset and the text after it.
+fn page_ref_at_cursor(buffer: &gtk::TextBuffer) -> Option<(i32, String)> {
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut line_start = cursor;
+    line_start.set_line_offset(0);
+
+    let line = buffer.text(&line_start, &cursor, false);
+    let (offset, query) = page_ref_query(&line)?;
+    Some((line_start.offset() + offset, query.to_string()))
+}
+
+fn update_completion(
+    text_view: &gtk::TextView,
+    popover: &gtk::Popover,
+    list: &gtk::ListBox,
+    completion: &mut Completion,
+) {
+    let Some((_, query)) = page_ref_at_cursor(&text_view.buffer()) else {
+        completion.pages = None;
+        popover.popdown();
+        return;
+    };
+
+    let pages = completion.pages.get_or_insert_with(|| {
+        let Some(graph) = Settings::load().logseq_graph() else {
+            return vec![];
+        };
+        page_names(&graph).unwrap_or_else(|err| {
+            warn!("Failed to read the pages of {}: {}", graph.display(), err);
+            vec![]
+        })
+    });
+
+    completion.shown = complete(pages, &query)
+        .into_iter()
+        .take(MAX_COMPLETIONS)
+        .map(String::from)
+        .collect();
+    if completion.shown.is_empty() {
+        popover.popdown();
+        return;
+    }
+
+    list.remove_all();
+    for name in completion.shown.iter() {
+        let label = gtk::Label::builder().label(name).xalign(0.0).build();
+        list.append(&label);
+    }
+    list.select_row(list.row_at_index(0).as_ref());
+
+    let buffer = text_view.buffer();
+    let location = text_view.iter_location(&buffer.iter_at_mark(&buffer.get_insert()));
+    let (x, y) =
+        text_view.buffer_to_window_coords(gtk::TextWindowType::Widget, location.x(), location.y());
+    popover.set_pointing_to(Some(&gdk::Rectangle::new(x, y, 1, location.height())));
+    popover.popup();
+}
+
+/// Replaces the `[[` being typed with a reference to `name`.
+fn accept(text_view: &gtk::TextView, name: &str) {
+    let buffer = text_view.buffer();
+    let Some((offset, _)) = page_ref_at_cursor(&buffer) else {
+        return;
+    };
+
+    let mut end = buffer.iter_at_mark(&buffer.get_insert());
+    let mut closing = end;
+    closing.forward_chars(2);
+    if buffer.text(&end, &closing, false) == "]]" {
+        end = closing;
+    }
+
+    buffer.begin_user_action();
+    let mut start = buffer.iter_at_offset(offset);
+    buffer.delete(&mut start, &mut end);
+    buffer.insert(&mut start, &format!("[[{}]]", name));
+    buffer.place_cursor(&start);
+    buffer.end_user_action();
+}